@@ -0,0 +1,492 @@
+use bevy::prelude::*;
+
+use crate::{
+    chunk::{ChunkData, FaceDirection},
+    light::LightLevel,
+    mesher::QuadSink,
+    voxel::Voxel,
+    world::ChunkMap,
+};
+
+const SIZE: usize = ChunkData::SIZE;
+
+/// Width of a chunk together with the voxel on either side of it.
+const PADDED: usize = SIZE + 2;
+
+/// One bit per voxel along an axis, indexed by the two remaining coordinates.
+type AxisColumns = [u32; SIZE * SIZE];
+
+/// Rows of visible faces for every slice along a face axis.
+type FacePlanes = [[u32; SIZE]; SIZE];
+
+/// What a face is drawn with, faces only merge when all of it matches.
+type FaceKey = (Voxel, [u8; 4], LightLevel);
+
+impl ChunkData {
+    /// Builds a greedy mesh using bitmasks instead of per-voxel neighbor lookups.
+    ///
+    /// Since a chunk is exactly 32 voxels wide, every column along an axis fits
+    /// in a `u32`. Exposed faces of a whole column are found with a shift and a
+    /// mask, using one padding bit taken from the neighboring chunk, and the
+    /// resulting face planes are merged greedily slice by slice with the same
+    /// bit tricks, as long as voxel type, ambient occlusion and light match.
+    ///
+    /// Opaque voxels are hidden only by opaque voxels, while every see-through
+    /// voxel type present gets its own columns, hidden by opaque voxels and by
+    /// the see-through types [`Voxel::is_face_visible`] hides it behind.
+    pub(crate) fn generate_binary_mesh(&self, chunk_map: &ChunkMap, builder: &mut impl QuadSink) {
+        let neighborhood = Neighborhood::new(self, chunk_map);
+        let (opaque, see_through) = neighborhood.voxel_columns(self.voxels().palette());
+
+        // See-through voxels are hidden by opaque voxels, their own type and
+        // whichever other see-through types they don't show against
+        let see_through: Vec<_> = see_through
+            .iter()
+            .map(|(voxel, columns)| {
+                let hiding: Vec<_> = see_through
+                    .iter()
+                    .filter(|(other, _)| !voxel.is_face_visible(*other))
+                    .map(|(_, other_columns)| other_columns)
+                    .collect();
+
+                let occluders = std::array::from_fn::<_, 3, _>(|axis| {
+                    std::array::from_fn(|i| {
+                        hiding
+                            .iter()
+                            .fold(opaque[axis][i], |occluder, other| occluder | other[axis][i])
+                    })
+                });
+                (*voxel, columns, occluders)
+            })
+            .collect();
+
+        let mut keys = [(Voxel::Air, [0; 4], LightLevel::default()); SIZE * SIZE];
+
+        for (face_direction, _) in FaceDirection::neighbor_offsets() {
+            let d = face_direction.axis();
+            let u = (d + 1) % 3;
+            let v = (d + 2) % 3;
+
+            // Faces of different voxel types never overlap, so they share planes
+            let mut planes = [[0; SIZE]; SIZE];
+
+            let border = neighborhood.border(face_direction);
+
+            let padding = border.map(|voxel| voxel.is_opaque());
+            collect_faces(
+                &opaque[d],
+                &opaque[d],
+                &padding,
+                face_direction,
+                &mut planes,
+            );
+
+            for (voxel, columns, occluders) in &see_through {
+                let padding = border.map(|other| !voxel.is_face_visible(other));
+                collect_faces(
+                    &columns[d],
+                    &occluders[d],
+                    &padding,
+                    face_direction,
+                    &mut planes,
+                );
+            }
+
+            for (slice, plane) in planes.iter_mut().enumerate() {
+                if plane.iter().all(|row| *row == 0) {
+                    continue;
+                }
+
+                for (cv, row) in plane.iter().enumerate() {
+                    let mut bits = *row;
+                    while bits != 0 {
+                        let cu = bits.trailing_zeros() as usize;
+                        bits &= bits - 1;
+
+                        let mut position = IVec3::ZERO;
+                        position[d] = slice as i32;
+                        position[u] = cu as i32;
+                        position[v] = cv as i32;
+
+                        keys[cu + cv * SIZE] =
+                            neighborhood.face_key(padded_index(position), face_direction);
+                    }
+                }
+
+                greedy_merge_plane(plane, &keys, |start_u, start_v, width, height, key| {
+                    let mut position = Vec3::ZERO;
+                    position[d] = slice as f32;
+                    position[u] = start_u as f32;
+                    position[v] = start_v as f32;
+
+                    let mut size = Vec3::ONE;
+                    size[u] = width as f32;
+                    size[v] = height as f32;
+
+                    let (voxel, ao, light) = key;
+                    builder.add_quad(position, size, face_direction, voxel, ao, light);
+                });
+            }
+        }
+    }
+}
+
+/// Adds the faces of `columns` not hidden by `occluders` to `planes`.
+///
+/// Both are the columns along the axis of `face_direction`, and `padding`
+/// tells which voxels just outside the chunk occlude.
+fn collect_faces(
+    columns: &AxisColumns,
+    occluders: &AxisColumns,
+    padding: &[bool; SIZE * SIZE],
+    face_direction: FaceDirection,
+    planes: &mut FacePlanes,
+) {
+    let positive = face_direction.offset()[face_direction.axis()] > 0;
+
+    for cv in 0..SIZE {
+        for cu in 0..SIZE {
+            let column = columns[cu + cv * SIZE];
+            if column == 0 {
+                continue;
+            }
+
+            let occluder = occluders[cu + cv * SIZE];
+            let pad = padding[cu + cv * SIZE] as u32;
+            let mut visible = if positive {
+                column & !((occluder >> 1) | (pad << (SIZE - 1)))
+            } else {
+                column & !((occluder << 1) | pad)
+            };
+
+            while visible != 0 {
+                let slice = visible.trailing_zeros() as usize;
+                visible &= visible - 1;
+
+                planes[slice][cv] |= 1 << cu;
+            }
+        }
+    }
+}
+
+/// Index of `position`, relative to the meshed chunk, in the arrays of a
+/// [`Neighborhood`].
+fn padded_index(position: IVec3) -> usize {
+    (position.x + 1) as usize
+        + (position.y + 1) as usize * PADDED
+        + (position.z + 1) as usize * PADDED * PADDED
+}
+
+/// Distance between the indices of two voxels `offset` apart in the arrays of
+/// a [`Neighborhood`].
+fn padded_stride(offset: IVec3) -> isize {
+    offset.x as isize
+        + offset.y as isize * PADDED as isize
+        + offset.z as isize * (PADDED * PADDED) as isize
+}
+
+/// A chunk together with the layer of voxels around it, see [`padded_index`].
+///
+/// Voxels and light are copied out of the chunk and its 26 neighbors once,
+/// after which looking up what a face is drawn with costs a few array reads
+/// instead of a palette lookup and a chunk map lookup per sample.
+struct Neighborhood {
+    /// Voxels, with missing and not yet generated chunks treated as air.
+    voxels: Box<[Voxel]>,
+    /// Light, with missing and not yet generated chunks fully sky lit, the
+    /// same as [`ChunkData::get_neighbor_light`].
+    light: Box<[LightLevel]>,
+    opaque: Box<[bool]>,
+}
+
+impl Neighborhood {
+    fn new(chunk: &ChunkData, chunk_map: &ChunkMap) -> Self {
+        const VOLUME: usize = PADDED * PADDED * PADDED;
+        let mut voxels = vec![Voxel::Air; VOLUME].into_boxed_slice();
+        let mut light = vec![LightLevel::SKY; VOLUME].into_boxed_slice();
+
+        for z in 0..SIZE {
+            for y in 0..SIZE {
+                let row = padded_index(IVec3::new(0, y as i32, z as i32));
+                chunk.copy_row(y, z, &mut voxels[row..], &mut light[row..]);
+            }
+        }
+
+        // Each neighbor covers a face, an edge or a corner of the layer
+        let size = SIZE as i32;
+        let covered = |offset: i32| match offset {
+            -1 => -1..=-1,
+            0 => 0..=size - 1,
+            _ => size..=size,
+        };
+
+        for index in 0..27 {
+            let offset = IVec3::new(index % 3, index / 3 % 3, index / 9) - 1;
+            if offset == IVec3::ZERO {
+                continue;
+            }
+
+            let Some(neighbor) = chunk_map
+                .get(&(chunk.position + offset))
+                .filter(|neighbor| neighbor.generated)
+            else {
+                continue;
+            };
+
+            for z in covered(offset.z) {
+                for y in covered(offset.y) {
+                    for x in covered(offset.x) {
+                        let position = IVec3::new(x, y, z);
+                        let [lx, ly, lz] = position
+                            .rem_euclid(IVec3::splat(size))
+                            .to_array()
+                            .map(|coordinate| coordinate as usize);
+
+                        voxels[padded_index(position)] = neighbor.get_voxel(lx, ly, lz);
+                        light[padded_index(position)] = neighbor.get_light(lx, ly, lz);
+                    }
+                }
+            }
+        }
+
+        let is_opaque = Voxel::ALL.map(|voxel| voxel.is_opaque());
+        let opaque = voxels
+            .iter()
+            .map(|voxel| is_opaque[*voxel as usize])
+            .collect();
+
+        Self {
+            voxels,
+            light,
+            opaque,
+        }
+    }
+
+    /// Packs the voxels of the chunk into bit columns along each of the three
+    /// axes, once for all opaque voxels and once per see-through voxel type of
+    /// `palette`.
+    ///
+    /// For axis `d` the column at `u + v * SIZE` (with `u = (d + 1) % 3` and
+    /// `v = (d + 2) % 3`) holds bit `i` when the voxel at depth `i` belongs to
+    /// the set.
+    fn voxel_columns(
+        &self,
+        palette: &[Voxel],
+    ) -> ([AxisColumns; 3], Vec<(Voxel, [AxisColumns; 3])>) {
+        let see_through_voxels: Vec<Voxel> = palette
+            .iter()
+            .copied()
+            .filter(|voxel| *voxel != Voxel::Air && !voxel.is_opaque())
+            .collect();
+
+        // Set every voxel type goes into by id, 0 for none, 1 for the opaque
+        // voxels and the see-through types from 2 on
+        let mut sets = [0; Voxel::ALL.len()];
+        for voxel in palette {
+            sets[*voxel as usize] = if voxel.is_opaque() {
+                1
+            } else {
+                see_through_voxels
+                    .iter()
+                    .position(|other| other == voxel)
+                    .map_or(0, |index| index + 2)
+            };
+        }
+
+        let mut columns = vec![[[0; SIZE * SIZE]; 3]; see_through_voxels.len() + 2];
+
+        for z in 0..SIZE {
+            for y in 0..SIZE {
+                let row = padded_index(IVec3::new(0, y as i32, z as i32));
+                for x in 0..SIZE {
+                    let set = sets[self.voxels[row + x] as usize];
+                    if set == 0 {
+                        continue;
+                    }
+
+                    let columns = &mut columns[set];
+                    columns[0][y + z * SIZE] |= 1 << x;
+                    columns[1][z + x * SIZE] |= 1 << y;
+                    columns[2][x + y * SIZE] |= 1 << z;
+                }
+            }
+        }
+
+        let mut columns = columns.into_iter().skip(1);
+        let opaque = columns.next().unwrap();
+
+        (
+            opaque,
+            see_through_voxels.into_iter().zip(columns).collect(),
+        )
+    }
+
+    /// Voxel, ambient occlusion and light of the face of the voxel at `index`
+    /// pointing in `face_direction`.
+    ///
+    /// Matches [`ChunkData::face_ambient_occlusion`] and
+    /// [`ChunkData::get_neighbor_light`].
+    fn face_key(&self, index: usize, face_direction: FaceDirection) -> FaceKey {
+        let front = index.wrapping_add_signed(padded_stride(face_direction.offset()));
+        let ao = face_direction.ambient_occlusion(|offset| {
+            self.opaque[front.wrapping_add_signed(padded_stride(offset))]
+        });
+
+        (self.voxels[index], ao, self.light[front])
+    }
+
+    /// Voxels of the layer just outside the chunk in `face_direction`,
+    /// indexed the same way as the columns of that axis.
+    fn border(&self, face_direction: FaceDirection) -> [Voxel; SIZE * SIZE] {
+        let d = face_direction.axis();
+        let u = (d + 1) % 3;
+        let v = (d + 2) % 3;
+        let depth = if face_direction.offset()[d] > 0 {
+            SIZE as i32
+        } else {
+            -1
+        };
+
+        std::array::from_fn(|index| {
+            let mut position = IVec3::ZERO;
+            position[d] = depth;
+            position[u] = (index % SIZE) as i32;
+            position[v] = (index / SIZE) as i32;
+
+            self.voxels[padded_index(position)]
+        })
+    }
+}
+
+/// Greedily merges the set bits of `plane` into rectangles of equal `keys`,
+/// indexed by `bit + row * SIZE`.
+///
+/// Runs of bits with the same key within a row are grown across the following
+/// rows while those hold the whole run with the same key. `emit` receives the
+/// start and extent of every rectangle as `(bit, row, width, height)`, and its
+/// key. The plane is consumed in the process.
+fn greedy_merge_plane(
+    plane: &mut [u32; SIZE],
+    keys: &[FaceKey; SIZE * SIZE],
+    mut emit: impl FnMut(usize, usize, usize, usize, FaceKey),
+) {
+    for row in 0..SIZE {
+        while plane[row] != 0 {
+            let bit = plane[row].trailing_zeros() as usize;
+            let key = keys[bit + row * SIZE];
+
+            let ones = (plane[row] >> bit).trailing_ones() as usize;
+            let width = (1..ones)
+                .find(|&i| keys[bit + i + row * SIZE] != key)
+                .unwrap_or(ones);
+            let run = if width >= SIZE {
+                u32::MAX
+            } else {
+                ((1 << width) - 1) << bit
+            };
+            plane[row] &= !run;
+
+            let mut height = 1;
+            while row + height < SIZE
+                && plane[row + height] & run == run
+                && keys[bit + (row + height) * SIZE..bit + width + (row + height) * SIZE]
+                    .iter()
+                    .all(|other| *other == key)
+            {
+                plane[row + height] &= !run;
+                height += 1;
+            }
+
+            emit(bit, row, width, height, key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+    use crate::{
+        chunk::ChunkTask,
+        light,
+        mesher::MeshingAlgorithm,
+        terrain::{TerrainGenerator, TerrainSettings},
+    };
+
+    /// Meshes the lit terrain around the origin of a fixed seed with every
+    /// algorithm, prints the time per chunk of the fastest of several passes
+    /// and asserts that the binary greedy mesher beats the other two.
+    ///
+    /// Ignored by default since timings are unreliable while other tests run
+    /// in parallel.
+    ///
+    /// Run with `cargo test --release mesh_benchmark -- --ignored --nocapture`.
+    #[test]
+    #[ignore = "benchmark"]
+    fn mesh_benchmark() {
+        const PASSES: u32 = 50;
+
+        let terrain_generator = TerrainGenerator::new(12345, TerrainSettings::default());
+        let positions: Vec<IVec3> = (-2..=2)
+            .flat_map(|x| (-2..=1).flat_map(move |y| (-2..=2).map(move |z| IVec3::new(x, y, z))))
+            .collect();
+
+        let mut chunk_map = ChunkMap::default();
+        for &position in &positions {
+            let mut chunk_task = ChunkTask::new(position, Entity::PLACEHOLDER);
+            chunk_task.generate(&terrain_generator);
+            chunk_map.insert(position, Arc::new(chunk_task.chunk_data));
+            light::light_new_chunk(&mut chunk_map, position, &terrain_generator);
+        }
+
+        // Surface and cave chunks with all their neighbors loaded
+        let meshed: Vec<&ChunkData> = positions
+            .iter()
+            .filter(|position| position.x.abs() <= 1 && position.z.abs() <= 1)
+            .filter(|position| (-1..=0).contains(&position.y))
+            .map(|position| chunk_map[position].as_ref())
+            .collect();
+
+        let mut per_chunk = Vec::new();
+        for algorithm in MeshingAlgorithm::ALL {
+            let mut fastest = Duration::MAX;
+            let mut vertex_count = 0;
+
+            for _ in 0..PASSES {
+                let start = Instant::now();
+                vertex_count = meshed
+                    .iter()
+                    .map(|chunk| chunk.generate_mesh(&chunk_map, algorithm).vertex_count())
+                    .sum::<usize>();
+                fastest = fastest.min(start.elapsed());
+            }
+
+            println!(
+                "{algorithm:?}: {:.3} ms per chunk, {} vertices per chunk",
+                fastest.as_secs_f64() * 1000.0 / meshed.len() as f64,
+                vertex_count / meshed.len()
+            );
+            per_chunk.push((algorithm, fastest / meshed.len() as u32));
+        }
+
+        let time_of = |algorithm| {
+            per_chunk
+                .iter()
+                .find_map(|&(measured, time)| (measured == algorithm).then_some(time))
+                .unwrap()
+        };
+        let binary_greedy = time_of(MeshingAlgorithm::BinaryGreedy);
+        assert!(
+            binary_greedy * 2 <= time_of(MeshingAlgorithm::FaceCulling),
+            "binary greedy meshing should be at least 2x faster than face culling"
+        );
+        assert!(
+            binary_greedy * 4 <= time_of(MeshingAlgorithm::Greedy),
+            "binary greedy meshing should be at least 4x faster than greedy meshing"
+        );
+    }
+}
@@ -25,6 +25,14 @@ impl Default for PlayerCamera {
     }
 }
 
+impl PlayerCamera {
+    /// Camera orientation for the current yaw and pitch.
+    pub fn rotation(&self) -> Quat {
+        Quat::from_axis_angle(Vec3::Y, self.yaw.to_radians())
+            * Quat::from_axis_angle(-Vec3::X, self.pitch.to_radians())
+    }
+}
+
 pub fn movement_axis(input: &Res<ButtonInput<KeyCode>>, plus: KeyCode, minus: KeyCode) -> f32 {
     let mut axis = 0.0;
     if input.pressed(plus) {
@@ -132,17 +140,13 @@ fn mouse_motion_system(
         options.pitch = options.pitch.clamp(-89.9, 89.9);
         // println!("pitch: {}, yaw: {}", options.pitch, options.yaw);
 
-        let yaw_radians = options.yaw.to_radians();
-        let pitch_radians = options.pitch.to_radians();
-
-        transform.rotation = Quat::from_axis_angle(Vec3::Y, yaw_radians)
-            * Quat::from_axis_angle(-Vec3::X, pitch_radians);
+        transform.rotation = options.rotation();
     }
 }
 
 #[derive(Resource, Default)]
-struct MouseLockState {
-    is_locked: bool,
+pub struct MouseLockState {
+    pub is_locked: bool,
 }
 
 fn cursor_lock_system(
@@ -1,23 +1,122 @@
-use std::sync::{Arc, RwLock};
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::Instant,
+};
 
-use bevy::{asset::RenderAssetUsages, prelude::*, render::mesh::Indices, tasks::Task};
+use bevy::{
+    prelude::*,
+    tasks::{Task, futures_lite::future},
+};
 
 use crate::{
+    light::{LightLevel, LightStorage},
+    mesher::{ChunkMesh, ChunkMeshBuilder, MeshingAlgorithm, QuadSink},
+    region::ChunkStorage,
     terrain::TerrainGenerator,
-    voxel::Voxel,
+    voxel::{MeshStats, Voxel},
+    voxel_storage::VoxelStorage,
     world::{ChunkMap, WorldManager},
 };
 
+/// A running [`ChunkTask`].
+///
+/// Dropping it, e.g. when the chunk is despawned or a newer task replaces it,
+/// cancels the task: it won't start if it hasn't yet, and otherwise stops at
+/// its next checkpoint.
 #[derive(Component)]
 pub struct ChunkThread {
-    pub thread: Task<ChunkTask>,
+    thread: Task<ChunkTask>,
+    cancelled: Arc<AtomicBool>,
+    metrics: ChunkTaskMetrics,
+    finished: bool,
+}
+
+impl ChunkThread {
+    /// `cancelled` is the [`ChunkTask::cancel_flag`] of the task `thread` runs.
+    pub fn new(
+        thread: Task<ChunkTask>,
+        cancelled: Arc<AtomicBool>,
+        metrics: ChunkTaskMetrics,
+    ) -> Self {
+        Self {
+            thread,
+            cancelled,
+            metrics,
+            finished: false,
+        }
+    }
+
+    /// Returns the finished task, or `None` while it is still running.
+    pub fn poll(&mut self) -> Option<ChunkTask> {
+        if self.finished {
+            return None;
+        }
+
+        let chunk_task = future::block_on(future::poll_once(&mut self.thread))?;
+        self.finished = true;
+        self.metrics.0.completed.fetch_add(1, Ordering::Relaxed);
+
+        Some(chunk_task)
+    }
+}
+
+impl Drop for ChunkThread {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+
+        // Tasks that already ran to completion did all their work, only the
+        // result goes unused
+        if self.thread.is_finished() {
+            self.metrics.0.discarded.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.cancelled.store(true, Ordering::Relaxed);
+            self.metrics.0.cancelled.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// How many chunk tasks had their result picked up, how many finished but had
+/// their result dropped unused, and how many were cancelled while they were
+/// still waiting or running.
+#[derive(Resource, Clone, Default)]
+pub struct ChunkTaskMetrics(Arc<ChunkTaskCounters>);
+
+#[derive(Default)]
+struct ChunkTaskCounters {
+    completed: AtomicU64,
+    discarded: AtomicU64,
+    cancelled: AtomicU64,
+}
+
+impl ChunkTaskMetrics {
+    pub fn completed(&self) -> u64 {
+        self.0.completed.load(Ordering::Relaxed)
+    }
+
+    pub fn discarded(&self) -> u64 {
+        self.0.discarded.load(Ordering::Relaxed)
+    }
+
+    pub fn cancelled(&self) -> u64 {
+        self.0.cancelled.load(Ordering::Relaxed)
+    }
 }
 
 pub struct ChunkTask {
     pub position: IVec3,
     pub entity: Entity,
     pub chunk_data: ChunkData,
-    pub mesh: Option<Mesh>,
+    pub mesh: Option<ChunkMesh>,
+    /// Whether `chunk_data` was generated by this task and still has to be
+    /// written back to the world.
+    pub newly_generated: bool,
+    /// Set by [`ChunkThread`] once nobody waits for the result anymore.
+    cancelled: Arc<AtomicBool>,
 }
 
 impl ChunkTask {
@@ -27,29 +126,110 @@ impl ChunkTask {
             entity,
             chunk_data: ChunkData::with_entity(position, entity),
             mesh: None,
+            newly_generated: false,
+            cancelled: Arc::default(),
         }
     }
 
-    pub fn generate(&mut self) {
-        let terrain_generator = TerrainGenerator::new(12345);
+    /// Flag the task checks between its stages, see [`ChunkThread`].
+    pub fn cancel_flag(&self) -> Arc<AtomicBool> {
+        self.cancelled.clone()
+    }
+
+    /// Whether the task was cancelled, after which its result is discarded
+    /// and the remaining work can be skipped.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Fills the chunk from its saved copy if there is one, otherwise from the
+    /// terrain generator.
+    pub fn load_or_generate(
+        &mut self,
+        terrain_generator: &TerrainGenerator,
+        chunk_storage: &ChunkStorage,
+    ) {
+        match chunk_storage.load_chunk(self.position) {
+            Ok(Some(voxels)) => {
+                self.newly_generated = true;
+                self.chunk_data.generated = true;
+                self.chunk_data.replace_voxels(voxels);
+                self.chunk_data.dirty = false;
+            }
+            Ok(None) => self.generate(terrain_generator),
+            Err(err) => {
+                error!("Failed to load chunk {}: {err}", self.position);
+                self.generate(terrain_generator);
+            }
+        }
+    }
+
+    pub fn generate(&mut self, terrain_generator: &TerrainGenerator) {
+        self.newly_generated = true;
+        self.chunk_data.generated = true;
+
+        if let Some(voxel) = terrain_generator.uniform_chunk(self.position) {
+            self.chunk_data.fill(voxel);
+        } else {
+            self.fill_terrain(terrain_generator);
+        }
+
+        if self.is_cancelled() {
+            return;
+        }
+
+        terrain_generator.place_ores(&mut self.chunk_data);
+        terrain_generator.place_features(&mut self.chunk_data);
+
+        // Filling starts out as air, so buried chunks only come out uniform
+        // once the unused voxel types are dropped
+        self.chunk_data.voxels.compact();
 
+        // Freshly generated terrain doesn't count as a modification
+        self.chunk_data.dirty = false;
+    }
+
+    fn fill_terrain(&mut self, terrain_generator: &TerrainGenerator) {
         for x in 0..ChunkData::SIZE {
             for z in 0..ChunkData::SIZE {
+                let world_x = (self.position.x * ChunkData::SIZE as i32) + x as i32;
+                let world_z = (self.position.z * ChunkData::SIZE as i32) + z as i32;
+                let column = terrain_generator.column(world_x, world_z);
+
                 for y in 0..ChunkData::SIZE {
-                    let voxel = terrain_generator.get_voxel(IVec3::new(
-                        (self.position.x * ChunkData::SIZE as i32) + x as i32,
-                        (self.position.y * ChunkData::SIZE as i32) + y as i32,
-                        (self.position.z * ChunkData::SIZE as i32) + z as i32,
-                    ));
+                    let voxel = terrain_generator.column_voxel(
+                        &column,
+                        IVec3::new(
+                            world_x,
+                            (self.position.y * ChunkData::SIZE as i32) + y as i32,
+                            world_z,
+                        ),
+                    );
                     self.chunk_data.set_voxel(voxel, x, y, z);
                 }
             }
         }
     }
 
-    pub fn mesh(&mut self, chunk_map: Arc<RwLock<ChunkMap>>) {
-        let lock = chunk_map.read().expect("Failed to acquire read lock");
-        self.mesh = Some(self.chunk_data.generate_mesh(&lock));
+    /// Meshes the chunk from `neighborhood`, a snapshot of the chunk and the
+    /// chunks around it, leaving `mesh` empty when there is nothing to draw.
+    pub fn mesh(&mut self, neighborhood: &ChunkMap, algorithm: MeshingAlgorithm) {
+        if self.is_cancelled() {
+            return;
+        }
+
+        let Some(chunk_data) = neighborhood.get(&self.position) else {
+            self.mesh = None;
+            return;
+        };
+
+        if chunk_data.is_hidden(neighborhood) {
+            self.mesh = None;
+            return;
+        }
+
+        let mesh = chunk_data.generate_mesh(neighborhood, algorithm);
+        self.mesh = (mesh.vertex_count() > 0).then_some(mesh);
     }
 }
 
@@ -59,6 +239,26 @@ pub struct NeedsMesh;
 #[derive(Component)]
 pub struct MeshInProgress;
 
+/// Where a chunk entity is in its way from being requested to being drawn.
+///
+/// Chunks are generated as soon as they are in range, but only meshed once
+/// they are in view and their six neighbors are generated too, so faces along
+/// the border are never built against missing chunks.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChunkState {
+    /// Waiting in the `ChunkQueue` for its generation task.
+    Queued,
+    /// Being loaded or generated.
+    Generating,
+    /// Generated, but not meshed, either because it is out of view or because
+    /// a neighbor isn't generated yet.
+    Generated,
+    /// Being meshed, which may still show a previous mesh.
+    Meshing,
+    /// Meshed and drawn.
+    Ready,
+}
+
 #[derive(Component)]
 pub struct NeedsDespawn;
 
@@ -78,7 +278,12 @@ impl Chunk {
 pub struct ChunkData {
     entity: Entity,
     pub position: IVec3,
-    pub voxels: [Voxel; ChunkData::SIZE * ChunkData::SIZE * ChunkData::SIZE],
+    voxels: VoxelStorage,
+    /// Light is computed once the chunk is in the world, see `light`.
+    light: LightStorage,
+    /// Set once the terrain generator has filled the chunk.
+    pub generated: bool,
+    /// Set when voxels changed since the chunk was generated.
     pub dirty: bool,
 }
 
@@ -87,8 +292,10 @@ impl ChunkData {
 
     pub fn new(position: IVec3) -> Self {
         Self {
-            voxels: [Voxel::Air; Self::SIZE * Self::SIZE * Self::SIZE],
+            voxels: VoxelStorage::new(Self::SIZE * Self::SIZE * Self::SIZE, Voxel::Air),
+            light: LightStorage::default(),
             position,
+            generated: false,
             dirty: false,
             entity: Entity::PLACEHOLDER,
         }
@@ -99,40 +306,167 @@ impl ChunkData {
         Self { entity, ..new }
     }
 
+    pub fn entity(&self) -> Entity {
+        self.entity
+    }
+
     fn index(x: usize, y: usize, z: usize) -> usize {
         debug_assert!(x < Self::SIZE && y < Self::SIZE && z < Self::SIZE);
         x + y * Self::SIZE + z * Self::SIZE * Self::SIZE
     }
 
     pub fn get_voxel(&self, x: usize, y: usize, z: usize) -> Voxel {
-        self.voxels[Self::index(x, y, z)]
+        self.voxels.get(Self::index(x, y, z))
     }
 
     pub fn set_voxel(&mut self, voxel: Voxel, x: usize, y: usize, z: usize) {
-        self.voxels[Self::index(x, y, z)] = voxel;
+        self.voxels.set(Self::index(x, y, z), voxel);
+        self.dirty = true;
+    }
+
+    pub fn get_light(&self, x: usize, y: usize, z: usize) -> LightLevel {
+        self.light.get(Self::index(x, y, z))
+    }
+
+    pub fn set_light(&mut self, light: LightLevel, x: usize, y: usize, z: usize) {
+        self.light.set(Self::index(x, y, z), light);
+    }
+
+    /// Copies the voxels and light of the row along x at `y` and `z`.
+    pub fn copy_row(&self, y: usize, z: usize, voxels: &mut [Voxel], light: &mut [LightLevel]) {
+        let start = Self::index(0, y, z);
+        self.voxels.copy_to(start, &mut voxels[..Self::SIZE]);
+        self.light.copy_to(start, &mut light[..Self::SIZE]);
+    }
+
+    /// Lights every voxel of the chunk with `light`.
+    pub fn fill_light(&mut self, light: LightLevel) {
+        self.light = LightStorage::Uniform(light);
+    }
+
+    /// Sets every voxel of the chunk to `voxel`.
+    pub fn fill(&mut self, voxel: Voxel) {
+        self.voxels = VoxelStorage::new(Self::SIZE * Self::SIZE * Self::SIZE, voxel);
         self.dirty = true;
     }
 
-    pub fn generate_mesh(&self, chunk_map: &ChunkMap) -> Mesh {
-        let mut vertices: Vec<[f32; 3]> = Vec::new();
-        let mut colors: Vec<[f32; 4]> = Vec::new();
-        let mut indices: Vec<u32> = Vec::new();
-        let mut vertex_count: u32 = 0;
+    /// Returns the voxel filling the whole chunk, if it is made of a single type.
+    pub fn uniform_voxel(&self) -> Option<Voxel> {
+        self.voxels.uniform_voxel()
+    }
+
+    /// Whether meshing can be skipped because no face of the chunk is visible.
+    ///
+    /// That is the case for chunks made only of air, and for chunks made of a
+    /// single voxel type whose six neighbors are loaded and hide every face
+    /// along the shared border.
+    pub fn is_hidden(&self, chunk_map: &ChunkMap) -> bool {
+        match self.uniform_voxel() {
+            Some(Voxel::Air) => true,
+            Some(voxel) => FaceDirection::neighbor_offsets().all(|(face_direction, offset)| {
+                chunk_map
+                    .get(&(self.position + offset))
+                    .is_some_and(|neighbor| {
+                        neighbor.is_border_covering(face_direction.opposite(), voxel)
+                    })
+            }),
+            None => false,
+        }
+    }
+
+    /// Whether every voxel on the side of the chunk facing `face_direction`
+    /// hides the faces of `voxel` touching it.
+    fn is_border_covering(&self, face_direction: FaceDirection, voxel: Voxel) -> bool {
+        if let Some(border) = self.uniform_voxel() {
+            return !voxel.is_face_visible(border);
+        }
+
+        let d = face_direction.axis();
+        let u = (d + 1) % 3;
+        let v = (d + 2) % 3;
+        let depth = if face_direction.offset()[d] > 0 {
+            Self::SIZE - 1
+        } else {
+            0
+        };
+
+        (0..Self::SIZE).all(|j| {
+            (0..Self::SIZE).all(|i| {
+                let mut position = [0; 3];
+                position[d] = depth;
+                position[u] = i;
+                position[v] = j;
+
+                !voxel.is_face_visible(self.get_voxel(position[0], position[1], position[2]))
+            })
+        })
+    }
+
+    pub fn voxels(&self) -> &VoxelStorage {
+        &self.voxels
+    }
+
+    pub fn replace_voxels(&mut self, voxels: VoxelStorage) {
+        debug_assert_eq!(voxels.len(), Self::SIZE * Self::SIZE * Self::SIZE);
+        self.voxels = voxels;
+        self.dirty = true;
+    }
+
+    /// Approximate heap memory used by the voxels and light of this chunk in bytes.
+    pub fn heap_size(&self) -> usize {
+        self.voxels.heap_size() + self.light.heap_size()
+    }
+
+    pub fn generate_mesh(&self, chunk_map: &ChunkMap, algorithm: MeshingAlgorithm) -> ChunkMesh {
+        let mut builder = ChunkMeshBuilder::default();
+        self.generate_quads(chunk_map, algorithm, &mut builder);
+        builder.build()
+    }
+
+    /// Feeds the quads [`Self::generate_mesh`] is built from to `sink`.
+    pub fn generate_quads(
+        &self,
+        chunk_map: &ChunkMap,
+        algorithm: MeshingAlgorithm,
+        sink: &mut impl QuadSink,
+    ) {
+        match algorithm {
+            MeshingAlgorithm::FaceCulling => self.generate_culled_mesh(chunk_map, sink),
+            MeshingAlgorithm::Greedy => self.generate_greedy_mesh(chunk_map, sink),
+            MeshingAlgorithm::BinaryGreedy => self.generate_binary_mesh(chunk_map, sink),
+        }
+    }
+
+    pub fn generate_mesh_with_stats(
+        &self,
+        chunk_map: &ChunkMap,
+        algorithm: MeshingAlgorithm,
+    ) -> (ChunkMesh, MeshStats) {
+        let start = Instant::now();
 
+        let mesh = self.generate_mesh(chunk_map, algorithm);
+
+        let stats = MeshStats {
+            vertex_count: mesh.vertex_count(),
+            triangle_count: mesh.triangle_count(),
+            generated_time_ms: start.elapsed().as_secs_f32() * 1000.0,
+            solid_voxel_count: self.voxels.iter().filter(Voxel::is_solid).count(),
+            algorithm: format!("{algorithm:?}"),
+        };
+
+        (mesh, stats)
+    }
+
+    fn generate_culled_mesh(&self, chunk_map: &ChunkMap, builder: &mut impl QuadSink) {
         for x in 0..Self::SIZE {
             for y in 0..Self::SIZE {
                 for z in 0..Self::SIZE {
                     let voxel = self.get_voxel(x, y, z);
 
-                    if voxel.is_solid() {
+                    if voxel != Voxel::Air {
                         self.add_exposed_faces(
-                            &mut vertices,
-                            &mut colors,
-                            &mut indices,
-                            &mut vertex_count,
-                            x as i32,
-                            y as i32,
-                            z as i32,
+                            builder,
+                            IVec3::new(x as i32, y as i32, z as i32),
                             voxel,
                             chunk_map,
                         );
@@ -140,90 +474,40 @@ impl ChunkData {
                 }
             }
         }
-
-        self.create_bevy_mesh(vertices, colors, indices)
-    }
-
-    // pub fn generate_mesh_with_stats(&self, world: &WorldManager) -> (Mesh, MeshStats) {
-    //     let start = Instant::now();
-    //
-    //     let mesh = self.generate_mesh(world);
-    //
-    //     let stats = MeshStats {
-    //         vertex_count: mesh.count_vertices(),
-    //         triangle_count: mesh.triangles().iter().len(),
-    //         generated_time_ms: start.elapsed().as_secs_f32() * 1000.0,
-    //         algorithm: "Face Culling".to_string(),
-    //         ..Default::default()
-    //     };
-    //
-    //     (mesh, stats)
-    // }
-
-    fn create_bevy_mesh(
-        &self,
-        vertices: Vec<[f32; 3]>,
-        colors: Vec<[f32; 4]>,
-        indices: Vec<u32>,
-    ) -> Mesh {
-        let mut mesh = Mesh::new(
-            bevy::render::mesh::PrimitiveTopology::TriangleList,
-            RenderAssetUsages::MAIN_WORLD | RenderAssetUsages::RENDER_WORLD,
-        );
-
-        // Set vertex locations in the world
-        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
-
-        // Set colors
-        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
-
-        // Set the triangle indices
-        mesh.insert_indices(Indices::U32(indices));
-
-        // Compute normals
-        mesh.compute_normals();
-
-        mesh
     }
 
     fn add_exposed_faces(
         &self,
-        vertices: &mut Vec<[f32; 3]>,
-        colors: &mut Vec<[f32; 4]>,
-        indices: &mut Vec<u32>,
-        vertex_count: &mut u32,
-        x: i32,
-        y: i32,
-        z: i32,
+        builder: &mut impl QuadSink,
+        position: IVec3,
         voxel: Voxel,
         chunk_map: &ChunkMap,
     ) {
-        let fx = x as f32;
-        let fy = y as f32;
-        let fz = z as f32;
-
         for (face_direction, offset) in FaceDirection::neighbor_offsets() {
-            let x = x + offset.x;
-            let y = y + offset.y;
-            let z = z + offset.z;
-
-            if !self.get_neighbor_voxel(x, y, z, chunk_map).is_solid() {
-                self.add_cube_face_to_mesh(
-                    vertices,
-                    colors,
-                    indices,
-                    vertex_count,
-                    fx,
-                    fy,
-                    fz,
+            let neighbor = position + offset;
+
+            if voxel.is_face_visible(
+                self.get_neighbor_voxel(neighbor.x, neighbor.y, neighbor.z, chunk_map),
+            ) {
+                let ao = self.face_ambient_occlusion(position, face_direction, chunk_map);
+                let light = self.get_neighbor_light(neighbor.x, neighbor.y, neighbor.z, chunk_map);
+                builder.add_quad(
+                    position.as_vec3(),
+                    Vec3::ONE,
                     face_direction,
                     voxel,
+                    ao,
+                    light,
                 );
             }
         }
     }
 
-    fn get_neighbor_voxel(&self, x: i32, y: i32, z: i32, chunk_map: &ChunkMap) -> Voxel {
+    /// Voxel at a position relative to this chunk, which may lie in a neighbor.
+    ///
+    /// Positions in chunks that aren't generated yet are air, like those in
+    /// chunks that aren't loaded at all.
+    pub(crate) fn get_neighbor_voxel(&self, x: i32, y: i32, z: i32, chunk_map: &ChunkMap) -> Voxel {
         if x >= 0
             && y >= 0
             && z >= 0
@@ -243,49 +527,49 @@ impl ChunkData {
         let chunk_pos = WorldManager::world_to_chunk_pos(&world_pos);
         let local_pos = WorldManager::world_to_local_pos(&world_pos);
 
-        if let Some(chunk) = chunk_map.get(&chunk_pos) {
-            chunk.get_voxel(
+        match chunk_map.get(&chunk_pos) {
+            Some(chunk) if chunk.generated => chunk.get_voxel(
                 local_pos.x as usize,
                 local_pos.y as usize,
                 local_pos.z as usize,
-            )
-        } else {
-            Voxel::Air // No chunk exists here
+            ),
+            _ => Voxel::Air,
         }
     }
 
-    fn add_cube_face_to_mesh(
+    /// Light at a position relative to this chunk, which may lie in a neighbor.
+    ///
+    /// Positions in chunks that aren't generated yet are fully sky lit, so faces
+    /// along the edge of the loaded world don't flash dark until they arrive.
+    pub(crate) fn get_neighbor_light(
         &self,
-        vertices: &mut Vec<[f32; 3]>,
-        colors: &mut Vec<[f32; 4]>,
-        indices: &mut Vec<u32>,
-        vertex_count: &mut u32,
-        x: f32,
-        y: f32,
-        z: f32,
-        face_direction: FaceDirection,
-        voxel: Voxel,
-    ) {
-        let face_verts = face_direction.face();
-
-        let material = voxel.get_material();
-        let color_array = material.color.to_srgba().to_f32_array();
-
-        for vertex in face_verts.iter() {
-            vertices.push([
-                vertex[0] + x, // X coordinate + voxel X position
-                vertex[1] + y, // Y coordinate + voxel Y position
-                vertex[2] + z, // Z coordinate + voxel Z position
-            ]);
-
-            colors.push(color_array);
+        x: i32,
+        y: i32,
+        z: i32,
+        chunk_map: &ChunkMap,
+    ) -> LightLevel {
+        if x >= 0
+            && y >= 0
+            && z >= 0
+            && x < Self::SIZE as i32
+            && y < Self::SIZE as i32
+            && z < Self::SIZE as i32
+        {
+            return self.get_light(x as usize, y as usize, z as usize);
         }
 
-        for &index in FACE_INDICES.iter() {
-            indices.push(index + *vertex_count);
-        }
+        let world_pos = self.position * Self::SIZE as i32 + IVec3::new(x, y, z);
+        let chunk_pos = WorldManager::world_to_chunk_pos(&world_pos);
+        let local_pos = WorldManager::world_to_local_pos(&world_pos);
 
-        *vertex_count += 4;
+        match chunk_map.get(&chunk_pos) {
+            Some(chunk) if chunk.generated => chunk.get_light(
+                local_pos.x as usize,
+                local_pos.y as usize,
+                local_pos.z as usize,
+            ),
+            _ => LightLevel::SKY,
+        }
     }
 
     pub fn get_world_transform(&self) -> Transform {
@@ -297,8 +581,8 @@ impl ChunkData {
     }
 }
 
-#[derive(Clone, Copy)]
-enum FaceDirection {
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FaceDirection {
     PosX,
     NegX,
     PosY,
@@ -308,7 +592,7 @@ enum FaceDirection {
 }
 
 impl FaceDirection {
-    fn face(&self) -> [[f32; 3]; 4] {
+    pub(crate) fn face(&self) -> [[f32; 3]; 4] {
         match self {
             FaceDirection::PosX => FACE_VERTICES[0],
             FaceDirection::NegX => FACE_VERTICES[1],
@@ -319,7 +603,57 @@ impl FaceDirection {
         }
     }
 
-    fn neighbor_offsets() -> impl Iterator<Item = (FaceDirection, IVec3)> {
+    /// Index of the axis this face points along (0 = X, 1 = Y, 2 = Z).
+    pub(crate) fn axis(&self) -> usize {
+        match self {
+            FaceDirection::PosX | FaceDirection::NegX => 0,
+            FaceDirection::PosY | FaceDirection::NegY => 1,
+            FaceDirection::PosZ | FaceDirection::NegZ => 2,
+        }
+    }
+
+    /// Face pointing along `axis` (0 = X, 1 = Y, 2 = Z), towards positive or
+    /// negative coordinates.
+    pub fn from_axis(axis: usize, positive: bool) -> FaceDirection {
+        match (axis, positive) {
+            (0, true) => FaceDirection::PosX,
+            (0, false) => FaceDirection::NegX,
+            (1, true) => FaceDirection::PosY,
+            (1, false) => FaceDirection::NegY,
+            (2, true) => FaceDirection::PosZ,
+            (2, false) => FaceDirection::NegZ,
+            _ => panic!("invalid axis {axis}"),
+        }
+    }
+
+    /// Unit offset towards the voxel this face looks at.
+    pub fn offset(&self) -> IVec3 {
+        match self {
+            FaceDirection::PosX => IVec3::X,
+            FaceDirection::NegX => IVec3::NEG_X,
+            FaceDirection::PosY => IVec3::Y,
+            FaceDirection::NegY => IVec3::NEG_Y,
+            FaceDirection::PosZ => IVec3::Z,
+            FaceDirection::NegZ => IVec3::NEG_Z,
+        }
+    }
+
+    pub(crate) fn opposite(&self) -> FaceDirection {
+        match self {
+            FaceDirection::PosX => FaceDirection::NegX,
+            FaceDirection::NegX => FaceDirection::PosX,
+            FaceDirection::PosY => FaceDirection::NegY,
+            FaceDirection::NegY => FaceDirection::PosY,
+            FaceDirection::PosZ => FaceDirection::NegZ,
+            FaceDirection::NegZ => FaceDirection::PosZ,
+        }
+    }
+
+    pub(crate) fn normal(&self) -> [f32; 3] {
+        self.offset().as_vec3().to_array()
+    }
+
+    pub(crate) fn neighbor_offsets() -> impl Iterator<Item = (FaceDirection, IVec3)> {
         vec![
             (FaceDirection::PosX, IVec3::new(1, 0, 0)),
             (FaceDirection::NegX, IVec3::new(-1, 0, 0)),
@@ -332,7 +666,10 @@ impl FaceDirection {
     }
 }
 
-const FACE_INDICES: [u32; 6] = [0, 3, 1, 1, 3, 2];
+pub(crate) const FACE_INDICES: [u32; 6] = [0, 3, 1, 1, 3, 2];
+
+/// Same winding as [`FACE_INDICES`], but split along the 0-2 diagonal instead.
+pub(crate) const FLIPPED_FACE_INDICES: [u32; 6] = [0, 3, 2, 0, 2, 1];
 
 const FACE_VERTICES: [[[f32; 3]; 4]; 6] = [
     // PosX face (right)
@@ -378,3 +715,81 @@ const FACE_VERTICES: [[[f32; 3]; 4]; 6] = [
         [0.0, 0.0, 0.0],
     ],
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::tasks::TaskPool;
+
+    use crate::terrain::TerrainSettings;
+
+    fn chunk_thread(
+        task_pool: &TaskPool,
+        metrics: &ChunkTaskMetrics,
+        finishes: bool,
+    ) -> ChunkThread {
+        let chunk_task = ChunkTask::new(IVec3::ZERO, Entity::PLACEHOLDER);
+        let cancelled = chunk_task.cancel_flag();
+        let thread = task_pool.spawn(async move {
+            if !finishes {
+                future::pending::<()>().await;
+            }
+            chunk_task
+        });
+
+        ChunkThread::new(thread, cancelled, metrics.clone())
+    }
+
+    #[test]
+    fn chunk_task_metrics_count_how_tasks_ended() {
+        let task_pool = TaskPool::new();
+        let metrics = ChunkTaskMetrics::default();
+
+        // Picked up
+        let mut thread = chunk_thread(&task_pool, &metrics, true);
+        while thread.poll().is_none() {
+            std::thread::yield_now();
+        }
+        drop(thread);
+
+        // Finished, but dropped before it was picked up
+        let thread = chunk_thread(&task_pool, &metrics, true);
+        while !thread.thread.is_finished() {
+            std::thread::yield_now();
+        }
+        drop(thread);
+
+        // Dropped while still running
+        let thread = chunk_thread(&task_pool, &metrics, false);
+        let cancelled = thread.cancelled.clone();
+        drop(thread);
+
+        assert!(cancelled.load(Ordering::Relaxed));
+        assert_eq!(
+            (
+                metrics.completed(),
+                metrics.discarded(),
+                metrics.cancelled()
+            ),
+            (1, 1, 1)
+        );
+    }
+
+    #[test]
+    fn generated_chunks_only_keep_voxel_types_they_use() {
+        // Without caves the chunk below the origin is buried in stone and ores
+        let settings = TerrainSettings {
+            cave_floor: f64::INFINITY,
+            ..default()
+        };
+        let terrain_generator = TerrainGenerator::new(12345, settings);
+
+        let mut chunk_task = ChunkTask::new(IVec3::NEG_Y, Entity::PLACEHOLDER);
+        chunk_task.generate(&terrain_generator);
+
+        let voxels = chunk_task.chunk_data.voxels();
+        for voxel in voxels.palette() {
+            assert!(voxels.iter().any(|other| other == *voxel), "{voxel:?}");
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use bevy::{
+    platform::collections::HashSet,
+    prelude::*,
+    render::primitives::{Frustum, Sphere},
+};
+
+use crate::{
+    camera::PlayerCamera,
+    chunk::{Chunk, ChunkData, NeedsMesh},
+    world::camera_chunk_pos,
+};
+
+/// How much further away chunks outside the view frustum count as, squared.
+const OUT_OF_VIEW_FACTOR: u32 = 4;
+
+/// How far the camera has to turn before priorities are recomputed, in
+/// radians.
+const REPRIORITIZE_ANGLE: f32 = 15f32.to_radians();
+
+/// Where the camera was and what it saw when priorities were computed.
+#[derive(Clone, Copy, Default)]
+struct ChunkView {
+    chunk_pos: IVec3,
+    /// Direction the camera was looking in, zero before the first view.
+    forward: Vec3,
+    frustum: Frustum,
+}
+
+impl ChunkView {
+    /// Whether `other` differs enough from this view to reorder the queue.
+    ///
+    /// Priorities only depend on the camera's chunk and on which chunks are in
+    /// view, so moving within a chunk or turning slightly keeps them close
+    /// enough to skip going through the whole queue.
+    fn is_outdated_by(&self, other: &ChunkView) -> bool {
+        self.chunk_pos != other.chunk_pos
+            || self.forward == Vec3::ZERO
+            || self.forward.angle_between(other.forward) > REPRIORITIZE_ANGLE
+    }
+
+    /// Priority of the chunk at `position`, lower values are handled first.
+    ///
+    /// Chunks are ordered by their squared distance to the camera's chunk,
+    /// with chunks outside the frustum pushed back so what the player looks at
+    /// fills in first, without leaving the chunks behind them for last.
+    fn priority(&self, position: IVec3) -> u32 {
+        let distance_squared = (position - self.chunk_pos).length_squared() as u32;
+
+        let size = ChunkData::SIZE as f32;
+        let bounds = Sphere {
+            center: ((position.as_vec3() + 0.5) * size).into(),
+            radius: size * 3f32.sqrt() / 2.0,
+        };
+
+        if self.frustum.intersects_sphere(&bounds, false) {
+            distance_squared
+        } else {
+            distance_squared * OUT_OF_VIEW_FACTOR
+        }
+    }
+}
+
+#[derive(PartialEq, Eq)]
+struct QueuedChunk {
+    priority: u32,
+    entity: Entity,
+    position: IVec3,
+}
+
+impl Ord for QueuedChunk {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Reversed, so the heap pops the lowest priority value first
+        other
+            .priority
+            .cmp(&self.priority)
+            .then_with(|| other.entity.cmp(&self.entity))
+    }
+}
+
+impl PartialOrd for QueuedChunk {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Chunks waiting for a generation or mesh task, most important first.
+///
+/// Chunks are queued once when they are tagged with [`NeedsMesh`] and stay
+/// queued across frames until a task slot frees up. Every priority is
+/// recomputed whenever the camera enters another chunk or turns far enough, so
+/// the queue keeps following the player instead of draining in the order
+/// chunks were requested.
+#[derive(Resource, Default)]
+pub struct ChunkQueue {
+    heap: BinaryHeap<QueuedChunk>,
+    queued: HashSet<Entity>,
+    view: ChunkView,
+}
+
+impl ChunkQueue {
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    /// Queues the chunk `entity` at `position`, unless it already is.
+    pub fn push(&mut self, entity: Entity, position: IVec3) {
+        if self.queued.insert(entity) {
+            self.heap.push(QueuedChunk {
+                priority: self.view.priority(position),
+                entity,
+                position,
+            });
+        }
+    }
+
+    /// Removes and returns the most important chunk.
+    pub fn pop(&mut self) -> Option<(Entity, IVec3)> {
+        let chunk = self.heap.pop()?;
+        self.queued.remove(&chunk.entity);
+
+        Some((chunk.entity, chunk.position))
+    }
+
+    /// Recomputes every priority for a new view, dropping chunks `is_queued`
+    /// no longer accepts.
+    fn reprioritize(&mut self, view: ChunkView, is_queued: impl Fn(Entity) -> bool) {
+        self.view = view;
+
+        let mut chunks = std::mem::take(&mut self.heap).into_vec();
+        chunks.retain(|chunk| is_queued(chunk.entity));
+        for chunk in &mut chunks {
+            chunk.priority = view.priority(chunk.position);
+        }
+
+        self.queued = chunks.iter().map(|chunk| chunk.entity).collect();
+        self.heap = BinaryHeap::from(chunks);
+    }
+}
+
+/// Queues chunks newly tagged with [`NeedsMesh`] and keeps the queue ordered
+/// around the camera.
+pub(crate) fn queue_chunks(
+    mut chunk_queue: ResMut<ChunkQueue>,
+    player_camera: Query<(Ref<Transform>, &Frustum), With<PlayerCamera>>,
+    new_chunks: Query<(Entity, &Chunk), Added<NeedsMesh>>,
+    queued_chunks: Query<(), With<NeedsMesh>>,
+) {
+    let Ok((transform, frustum)) = player_camera.single() else {
+        return;
+    };
+
+    if transform.is_changed() {
+        let view = ChunkView {
+            chunk_pos: camera_chunk_pos(&transform),
+            forward: *transform.forward(),
+            frustum: *frustum,
+        };
+
+        if chunk_queue.view.is_outdated_by(&view) {
+            chunk_queue.reprioritize(view, |entity| queued_chunks.contains(entity));
+        }
+    }
+
+    for (entity, chunk) in new_chunks.iter() {
+        chunk_queue.push(entity, chunk.position);
+    }
+}
@@ -5,6 +5,13 @@ use bevy::{
     text::FontSmoothing,
 };
 
+use crate::{
+    chunk::{ChunkState, ChunkTaskMetrics, NeedsMesh},
+    chunk_queue::ChunkQueue,
+    mesher::MeshingAlgorithm,
+    world::{ChunkLoadingSettings, WorldManager},
+};
+
 pub struct DebugPlugin;
 
 impl Plugin for DebugPlugin {
@@ -28,7 +35,11 @@ impl Plugin for DebugPlugin {
             },
         })
         .add_plugins(WireframePlugin::default())
-        .add_systems(Update, debug_input_system);
+        .add_systems(Update, debug_input_system)
+        .add_systems(Update, cycle_meshing_algorithm)
+        .add_systems(Update, benchmark_meshing_algorithms)
+        .add_systems(Update, adjust_view_distance)
+        .add_systems(Update, log_chunk_task_metrics);
     }
 }
 
@@ -41,3 +52,92 @@ fn debug_input_system(
         info!("Wireframe mode: {}", wireframe_config.global);
     }
 }
+
+fn cycle_meshing_algorithm(
+    mut commands: Commands,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut meshing_algorithm: ResMut<MeshingAlgorithm>,
+    chunks: Query<(Entity, &ChunkState)>,
+) {
+    if keyboard_input.just_pressed(KeyCode::F2) {
+        *meshing_algorithm = meshing_algorithm.next();
+        info!("Meshing algorithm: {:?}", *meshing_algorithm);
+
+        for (entity, state) in chunks.iter() {
+            if matches!(state, ChunkState::Meshing | ChunkState::Ready) {
+                commands.entity(entity).try_insert(NeedsMesh);
+            }
+        }
+    }
+}
+
+/// Meshes every loaded chunk with each algorithm and logs the totals.
+fn benchmark_meshing_algorithms(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    world_manager: Res<WorldManager>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::F3) {
+        return;
+    }
+
+    let chunks = world_manager.chunks();
+    let voxel_memory: usize = chunks.values().map(|chunk| chunk.heap_size()).sum();
+    info!(
+        "Benchmarking meshing over {} chunks ({} KiB of voxel data)",
+        chunks.len(),
+        voxel_memory / 1024
+    );
+
+    for algorithm in MeshingAlgorithm::ALL {
+        let mut time_ms = 0.0;
+        let mut vertex_count = 0;
+        let mut triangle_count = 0;
+
+        for chunk in chunks.values() {
+            let (_, stats) = chunk.generate_mesh_with_stats(chunks, algorithm);
+            time_ms += stats.generated_time_ms;
+            vertex_count += stats.vertex_count;
+            triangle_count += stats.triangle_count;
+        }
+
+        info!("{algorithm:?}: {time_ms:.2}ms, {vertex_count} vertices, {triangle_count} triangles");
+    }
+}
+
+/// Shrinks or grows the horizontal view distance with F4 and F5.
+fn adjust_view_distance(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut loading_settings: ResMut<ChunkLoadingSettings>,
+) {
+    let change = if keyboard_input.just_pressed(KeyCode::F4) {
+        -1
+    } else if keyboard_input.just_pressed(KeyCode::F5) {
+        1
+    } else {
+        return;
+    };
+
+    loading_settings.horizontal_distance = (loading_settings.horizontal_distance + change).max(1);
+    info!(
+        "View distance: {} chunks",
+        loading_settings.horizontal_distance
+    );
+}
+
+/// Logs how many chunk tasks completed, had their result discarded, were
+/// cancelled and are waiting on F6.
+fn log_chunk_task_metrics(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    task_metrics: Res<ChunkTaskMetrics>,
+    chunk_queue: Res<ChunkQueue>,
+) {
+    if keyboard_input.just_pressed(KeyCode::F6) {
+        info!(
+            "Chunk tasks: {} completed, {} discarded, {} cancelled, {} queued",
+            task_metrics.completed(),
+            task_metrics.discarded(),
+            task_metrics.cancelled(),
+            chunk_queue.len()
+        );
+    }
+}
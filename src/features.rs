@@ -0,0 +1,262 @@
+use bevy::math::IVec3;
+
+use crate::{
+    chunk::ChunkData,
+    random::SplitMix64,
+    terrain::{Biome, TerrainGenerator},
+    voxel::Voxel,
+};
+
+const SIZE: i32 = ChunkData::SIZE as i32;
+
+/// Salt for the feature generators, distinct from the ones used by ores.
+const FEATURE_SALT: u64 = 0x46454154;
+
+/// Candidate positions rolled for a feature in every chunk column.
+const FEATURE_ATTEMPTS: u32 = 8;
+
+/// Furthest any feature reaches above or below the surface it stands on.
+const MAX_FEATURE_HEIGHT: i32 = 8;
+const MAX_FEATURE_DEPTH: i32 = 2;
+
+impl TerrainGenerator {
+    /// Decorates the surface in `chunk_data` with trees, cacti and boulders.
+    ///
+    /// Features are rolled per chunk column with a generator seeded from the
+    /// world seed and the column position, and only depend on the terrain
+    /// below them, which is a pure function of the position. Since none is
+    /// wider than a chunk, every chunk replays the features of its own and its
+    /// 8 neighboring columns and keeps the voxels that land inside it, so a
+    /// tree overlapping a chunk border is whole on both sides however the
+    /// chunks are loaded.
+    pub fn place_features(&self, chunk_data: &mut ChunkData) {
+        let origin = chunk_data.position * SIZE;
+
+        let (lowest_surface, highest_surface) = self.surface_range();
+        if origin.y + SIZE <= lowest_surface.floor() as i32 - MAX_FEATURE_DEPTH
+            || origin.y > highest_surface.floor() as i32 + MAX_FEATURE_HEIGHT
+        {
+            return;
+        }
+
+        // Features only ever grow into air
+        if chunk_data
+            .uniform_voxel()
+            .is_some_and(|voxel| voxel != Voxel::Air)
+        {
+            return;
+        }
+
+        for cx in -1..=1 {
+            for cz in -1..=1 {
+                let column = IVec3::new(chunk_data.position.x + cx, 0, chunk_data.position.z + cz);
+                let mut random = SplitMix64::at(self.seed(), FEATURE_SALT, column);
+
+                for _ in 0..FEATURE_ATTEMPTS {
+                    let x = column.x * SIZE + random.range(0, SIZE);
+                    let z = column.z * SIZE + random.range(0, SIZE);
+                    let roll = random.next_f64();
+
+                    let terrain_column = self.column(x, z);
+                    let ground = IVec3::new(x, terrain_column.height.floor() as i32, z);
+
+                    // Nothing grows over caves breaking through the surface, or under water
+                    if !self.column_voxel(&terrain_column, ground).is_solid()
+                        || self.column_voxel(&terrain_column, ground + IVec3::Y) == Voxel::Water
+                    {
+                        continue;
+                    }
+
+                    let mut writer = FeatureWriter {
+                        chunk_data: &mut *chunk_data,
+                        origin,
+                    };
+
+                    match terrain_column.biome {
+                        Biome::Plains => {
+                            if roll < 0.35 {
+                                writer.tree(ground, &mut random);
+                            } else if roll < 0.40 {
+                                writer.boulder(ground, Voxel::Stone, &mut random);
+                            }
+                        }
+                        Biome::Forest => {
+                            if roll < 0.95 {
+                                writer.tree(ground, &mut random);
+                            }
+                        }
+                        Biome::Swamp => {
+                            if roll < 0.5 {
+                                writer.tree(ground, &mut random);
+                            }
+                        }
+                        Biome::Tundra => {
+                            if roll < 0.1 {
+                                writer.tree(ground, &mut random);
+                            } else if roll < 0.2 {
+                                writer.boulder(ground, Voxel::Stone, &mut random);
+                            }
+                        }
+                        Biome::Mountains => {
+                            if roll < 0.15 {
+                                writer.boulder(ground, Voxel::Stone, &mut random);
+                            }
+                        }
+                        Biome::Desert => {
+                            if roll < 0.15 {
+                                writer.cactus(ground, &mut random);
+                            } else if roll < 0.20 {
+                                writer.boulder(ground, Voxel::SandStone, &mut random);
+                            }
+                        }
+                        Biome::Ocean => {}
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Writes the parts of features that fall inside one chunk.
+struct FeatureWriter<'a> {
+    chunk_data: &'a mut ChunkData,
+    /// World position of the chunk's first voxel.
+    origin: IVec3,
+}
+
+impl FeatureWriter<'_> {
+    /// Sets the voxel at `world_pos` if it is inside the chunk and currently
+    /// one `replace` accepts.
+    fn set(&mut self, world_pos: IVec3, voxel: Voxel, replace: impl Fn(Voxel) -> bool) {
+        let local = world_pos - self.origin;
+        if local.cmplt(IVec3::ZERO).any() || local.cmpge(IVec3::splat(SIZE)).any() {
+            return;
+        }
+
+        let (x, y, z) = (local.x as usize, local.y as usize, local.z as usize);
+        if replace(self.chunk_data.get_voxel(x, y, z)) {
+            self.chunk_data.set_voxel(voxel, x, y, z);
+        }
+    }
+
+    fn tree(&mut self, ground: IVec3, random: &mut SplitMix64) {
+        let height = random.range(4, 7);
+        let top = ground.y + height;
+
+        // Two wide layers of leaves around the top of the trunk, capped by a
+        // narrow one, with the corners of the wide layers cut off
+        for y in top - 1..=top + 1 {
+            let radius: i32 = if y <= top { 2 } else { 1 };
+
+            for dx in -radius..=radius {
+                for dz in -radius..=radius {
+                    if radius > 1 && dx.abs() == radius && dz.abs() == radius {
+                        continue;
+                    }
+
+                    let position = IVec3::new(ground.x + dx, y, ground.z + dz);
+                    self.set(position, Voxel::Leaves, |voxel| voxel == Voxel::Air);
+                }
+            }
+        }
+
+        for y in ground.y + 1..=top {
+            self.set(IVec3::new(ground.x, y, ground.z), Voxel::Wood, |voxel| {
+                matches!(voxel, Voxel::Air | Voxel::Leaves)
+            });
+        }
+    }
+
+    fn cactus(&mut self, ground: IVec3, random: &mut SplitMix64) {
+        let height = random.range(2, 5);
+
+        for y in ground.y + 1..=ground.y + height {
+            self.set(IVec3::new(ground.x, y, ground.z), Voxel::Cactus, |voxel| {
+                voxel == Voxel::Air
+            });
+        }
+    }
+
+    /// A rough sphere half sunk into the ground.
+    fn boulder(&mut self, ground: IVec3, voxel: Voxel, random: &mut SplitMix64) {
+        let radius = random.range(1, MAX_FEATURE_DEPTH + 1);
+
+        for dx in -radius..=radius {
+            for dy in -radius..=radius {
+                for dz in -radius..=radius {
+                    let offset = IVec3::new(dx, dy, dz);
+                    if offset.length_squared() <= radius * radius + radius {
+                        self.set(ground + offset, voxel, |voxel| voxel == Voxel::Air);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::entity::Entity;
+
+    use super::*;
+    use crate::{chunk::ChunkTask, terrain::TerrainSettings};
+
+    fn voxels(chunk_data: &ChunkData) -> Vec<Voxel> {
+        let mut voxels = Vec::with_capacity(ChunkData::SIZE.pow(3));
+        for y in 0..ChunkData::SIZE {
+            for z in 0..ChunkData::SIZE {
+                for x in 0..ChunkData::SIZE {
+                    voxels.push(chunk_data.get_voxel(x, y, z));
+                }
+            }
+        }
+        voxels
+    }
+
+    /// Generates the chunks at `positions` one after the other.
+    fn generate(terrain_generator: &TerrainGenerator, positions: [IVec3; 2]) -> [Vec<Voxel>; 2] {
+        positions.map(|position| {
+            let mut chunk_task = ChunkTask::new(position, Entity::PLACEHOLDER);
+            chunk_task.generate(terrain_generator);
+            voxels(&chunk_task.chunk_data)
+        })
+    }
+
+    #[test]
+    fn trees_across_chunk_borders_do_not_depend_on_load_order() {
+        let terrain_generator = TerrainGenerator::new(12345, TerrainSettings::default());
+        let index = |x: usize, y: usize, z: usize| (y * ChunkData::SIZE + z) * ChunkData::SIZE + x;
+        let mut crossing_trees = 0;
+
+        for cx in -4..4 {
+            for cz in -4..4 {
+                // The chunk holding the surface where the two columns meet
+                let height = terrain_generator
+                    .column((cx + 1) * SIZE, cz * SIZE + SIZE / 2)
+                    .height;
+                let cy = (height.floor() as i32).div_euclid(SIZE);
+                let west = IVec3::new(cx, cy, cz);
+                let east = west + IVec3::X;
+
+                let [west_first, east_second] = generate(&terrain_generator, [west, east]);
+                let [east_first, west_second] = generate(&terrain_generator, [east, west]);
+                assert_eq!(west_first, west_second);
+                assert_eq!(east_first, east_second);
+
+                // Trunks in the last column of the west chunk with leaves
+                // reaching into the east chunk next to them
+                for y in 0..ChunkData::SIZE {
+                    for z in 0..ChunkData::SIZE {
+                        if west_first[index(ChunkData::SIZE - 1, y, z)] == Voxel::Wood
+                            && east_first[index(0, y, z)] == Voxel::Leaves
+                        {
+                            crossing_trees += 1;
+                        }
+                    }
+                }
+            }
+        }
+
+        assert!(crossing_trees > 0, "no tree crosses a chunk border");
+    }
+}
@@ -0,0 +1,248 @@
+use bevy::prelude::*;
+
+use crate::{
+    camera::{MouseLockState, PlayerCamera},
+    raycast::{RaycastHit, UnloadedChunks},
+    voxel::Voxel,
+    world::WorldManager,
+};
+
+/// How far away from the camera voxels can be broken or placed.
+const REACH: f32 = 8.0;
+
+const HOTBAR_KEYS: [KeyCode; 9] = [
+    KeyCode::Digit1,
+    KeyCode::Digit2,
+    KeyCode::Digit3,
+    KeyCode::Digit4,
+    KeyCode::Digit5,
+    KeyCode::Digit6,
+    KeyCode::Digit7,
+    KeyCode::Digit8,
+    KeyCode::Digit9,
+];
+
+pub struct InteractionPlugin;
+
+impl Plugin for InteractionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Hotbar>()
+            .init_resource::<TargetedVoxel>()
+            .add_systems(Startup, setup_hotbar_text)
+            .add_systems(
+                Update,
+                (
+                    select_hotbar_slot,
+                    update_hotbar_text,
+                    update_targeted_voxel,
+                    edit_targeted_voxel,
+                    draw_target_outline,
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Voxel types the player can place, selected with the number keys.
+#[derive(Resource)]
+pub struct Hotbar {
+    pub slots: Vec<Voxel>,
+    pub selected: usize,
+}
+
+impl Default for Hotbar {
+    fn default() -> Self {
+        Self {
+            slots: vec![
+                Voxel::Dirt,
+                Voxel::Grass,
+                Voxel::Stone,
+                Voxel::Sand,
+                Voxel::SandStone,
+                Voxel::Glowstone,
+                Voxel::Lamp,
+                Voxel::Wood,
+            ],
+            selected: 0,
+        }
+    }
+}
+
+impl Hotbar {
+    pub fn selected_voxel(&self) -> Voxel {
+        self.slots[self.selected]
+    }
+}
+
+/// Voxel the player camera is currently looking at, if any is within reach.
+#[derive(Resource, Default, Deref)]
+pub struct TargetedVoxel(Option<RaycastHit>);
+
+#[derive(Component)]
+struct HotbarText;
+
+fn setup_hotbar_text(mut commands: Commands) {
+    commands.spawn((
+        HotbarText,
+        Text::default(),
+        TextFont {
+            font_size: 24.0,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(12.0),
+            left: Val::Px(12.0),
+            ..default()
+        },
+    ));
+}
+
+fn select_hotbar_slot(keyboard_input: Res<ButtonInput<KeyCode>>, mut hotbar: ResMut<Hotbar>) {
+    for (slot, key) in HOTBAR_KEYS.iter().enumerate().take(hotbar.slots.len()) {
+        if keyboard_input.just_pressed(*key) {
+            hotbar.selected = slot;
+        }
+    }
+}
+
+fn update_hotbar_text(hotbar: Res<Hotbar>, mut text: Query<&mut Text, With<HotbarText>>) {
+    if !hotbar.is_changed() {
+        return;
+    }
+
+    for mut text in text.iter_mut() {
+        text.0 = format!("[{}] {:?}", hotbar.selected + 1, hotbar.selected_voxel());
+    }
+}
+
+fn update_targeted_voxel(
+    world_manager: Res<WorldManager>,
+    mut targeted_voxel: ResMut<TargetedVoxel>,
+    player_camera: Query<&Transform, With<PlayerCamera>>,
+) {
+    let Ok(camera) = player_camera.single() else {
+        return;
+    };
+
+    targeted_voxel.0 = world_manager.raycast(
+        camera.translation,
+        *camera.forward(),
+        REACH,
+        UnloadedChunks::Block,
+    );
+}
+
+fn edit_targeted_voxel(
+    mouse_input: Res<ButtonInput<MouseButton>>,
+    mouse_lock_state: Res<MouseLockState>,
+    hotbar: Res<Hotbar>,
+    targeted_voxel: Res<TargetedVoxel>,
+    mut world_manager: ResMut<WorldManager>,
+    player_camera: Query<&Transform, With<PlayerCamera>>,
+) {
+    if !mouse_lock_state.is_locked {
+        return;
+    }
+
+    let Some(hit) = **targeted_voxel else {
+        return;
+    };
+
+    if mouse_input.just_pressed(MouseButton::Left) {
+        world_manager.set_voxel(hit.position, Voxel::Air);
+    } else if mouse_input.just_pressed(MouseButton::Right) {
+        let position = hit.position + hit.face.offset();
+
+        // Don't place voxels inside the camera
+        let inside_camera = player_camera
+            .single()
+            .is_ok_and(|camera| camera.translation.floor().as_ivec3() == position);
+        if inside_camera {
+            return;
+        }
+
+        if world_manager
+            .get_voxel(position)
+            .is_some_and(|voxel| !voxel.is_solid())
+        {
+            world_manager.set_voxel(position, hotbar.selected_voxel());
+        }
+    }
+}
+
+fn draw_target_outline(targeted_voxel: Res<TargetedVoxel>, mut gizmos: Gizmos) {
+    let Some(hit) = **targeted_voxel else {
+        return;
+    };
+
+    // Slightly larger than a voxel so the outline isn't hidden by its faces
+    gizmos.cuboid(
+        Transform::from_translation(hit.position.as_vec3() + Vec3::splat(0.5))
+            .with_scale(Vec3::splat(1.005)),
+        Color::BLACK,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use crate::{
+        chunk::{ChunkData, FaceDirection},
+        terrain::TerrainGenerator,
+    };
+
+    #[test]
+    fn voxels_are_placed_against_the_targeted_face() {
+        let target = IVec3::new(4, 4, 4);
+
+        for face in [
+            FaceDirection::PosX,
+            FaceDirection::NegX,
+            FaceDirection::PosY,
+            FaceDirection::NegY,
+            FaceDirection::PosZ,
+            FaceDirection::NegZ,
+        ] {
+            let mut chunk_data = ChunkData::new(IVec3::ZERO);
+            chunk_data.generated = true;
+            chunk_data.set_voxel(Voxel::Stone, 4, 4, 4);
+            let mut world_manager = WorldManager::default();
+            world_manager.insert_generated(chunk_data, &TerrainGenerator::sunken());
+
+            // Aim at the target from a few voxels out in front of `face`
+            let origin = target.as_vec3() + Vec3::splat(0.5) + face.offset().as_vec3() * 3.0;
+            let hit = world_manager.raycast(
+                origin,
+                -face.offset().as_vec3(),
+                REACH,
+                UnloadedChunks::Block,
+            );
+            assert_eq!(
+                hit.map(|hit| (hit.position, hit.face)),
+                Some((target, face))
+            );
+
+            let mut mouse_input = ButtonInput::default();
+            mouse_input.press(MouseButton::Right);
+
+            let mut world = World::new();
+            world.insert_resource(mouse_input);
+            world.insert_resource(MouseLockState { is_locked: true });
+            world.insert_resource(Hotbar::default());
+            world.insert_resource(TargetedVoxel(hit));
+            world.insert_resource(world_manager);
+            world.run_system_once(edit_targeted_voxel).unwrap();
+
+            let world_manager = world.resource::<WorldManager>();
+            let placed = Hotbar::default().selected_voxel();
+            assert_eq!(
+                world_manager.get_voxel(target + face.offset()),
+                Some(placed)
+            );
+            assert_eq!(world_manager.get_voxel(target), Some(Voxel::Stone));
+        }
+    }
+}
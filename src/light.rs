@@ -0,0 +1,771 @@
+use std::{collections::VecDeque, sync::Arc};
+
+use bevy::{platform::collections::HashSet, prelude::*};
+
+use crate::{
+    chunk::{ChunkData, FaceDirection},
+    terrain::TerrainGenerator,
+    voxel::Voxel,
+    world::{ChunkMap, WorldManager},
+};
+
+const SIZE: usize = ChunkData::SIZE;
+
+/// Which of the two independent light values of a voxel is meant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LightChannel {
+    /// Light coming from the sky, which travels straight down without fading.
+    Sky,
+    /// Light emitted by voxels.
+    Block,
+}
+
+impl LightChannel {
+    const ALL: [LightChannel; 2] = [LightChannel::Sky, LightChannel::Block];
+
+    /// Level light of `level` has after spreading one voxel in `face_direction`.
+    fn spread(self, level: u8, face_direction: FaceDirection) -> u8 {
+        if self == LightChannel::Sky
+            && face_direction == FaceDirection::NegY
+            && level == LightLevel::MAX
+        {
+            level
+        } else {
+            level.saturating_sub(1)
+        }
+    }
+}
+
+/// Sky and block light of a voxel, 4 bits each, with sky light in the high
+/// nibble.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct LightLevel(u8);
+
+impl LightLevel {
+    pub const MAX: u8 = 15;
+
+    /// Full sky light, what the open sky and unloaded chunks are lit with.
+    pub const SKY: LightLevel = LightLevel(Self::MAX << 4);
+
+    pub fn new(sky: u8, block: u8) -> Self {
+        debug_assert!(sky <= Self::MAX && block <= Self::MAX);
+        Self(sky << 4 | block)
+    }
+
+    pub fn sky(self) -> u8 {
+        self.0 >> 4
+    }
+
+    pub fn block(self) -> u8 {
+        self.0 & 0xF
+    }
+
+    pub fn get(self, channel: LightChannel) -> u8 {
+        match channel {
+            LightChannel::Sky => self.sky(),
+            LightChannel::Block => self.block(),
+        }
+    }
+
+    pub fn with(self, channel: LightChannel, level: u8) -> Self {
+        match channel {
+            LightChannel::Sky => Self::new(level, self.block()),
+            LightChannel::Block => Self::new(self.sky(), level),
+        }
+    }
+
+    /// Brightness multiplier for vertex colors, from the brighter channel.
+    ///
+    /// Every level is 80% as bright as the one above, never going fully black.
+    pub fn brightness(self) -> f32 {
+        let level = self.sky().max(self.block());
+        0.8f32.powi((Self::MAX - level) as i32).max(0.05)
+    }
+}
+
+/// Light levels of every voxel of a chunk.
+///
+/// Chunks lit by a single level, like those deep underground or high up in the
+/// sky, don't allocate per voxel storage.
+#[derive(Clone, Debug)]
+pub enum LightStorage {
+    Uniform(LightLevel),
+    Mixed(Box<[LightLevel]>),
+}
+
+impl Default for LightStorage {
+    fn default() -> Self {
+        Self::Uniform(LightLevel::default())
+    }
+}
+
+impl LightStorage {
+    pub fn get(&self, index: usize) -> LightLevel {
+        match self {
+            LightStorage::Uniform(level) => *level,
+            LightStorage::Mixed(levels) => levels[index],
+        }
+    }
+
+    pub fn set(&mut self, index: usize, level: LightLevel) {
+        match self {
+            LightStorage::Uniform(uniform) if *uniform == level => {}
+            LightStorage::Uniform(uniform) => {
+                let mut levels = vec![*uniform; SIZE * SIZE * SIZE].into_boxed_slice();
+                levels[index] = level;
+                *self = LightStorage::Mixed(levels);
+            }
+            LightStorage::Mixed(levels) => levels[index] = level,
+        }
+    }
+
+    /// Copies the levels from `start` on into `out`.
+    pub fn copy_to(&self, start: usize, out: &mut [LightLevel]) {
+        match self {
+            LightStorage::Uniform(level) => out.fill(*level),
+            LightStorage::Mixed(levels) => out.copy_from_slice(&levels[start..start + out.len()]),
+        }
+    }
+
+    pub fn heap_size(&self) -> usize {
+        match self {
+            LightStorage::Uniform(_) => 0,
+            LightStorage::Mixed(levels) => levels.len() * size_of::<LightLevel>(),
+        }
+    }
+}
+
+/// Flood fills light through the loaded chunks of a [`ChunkMap`].
+///
+/// Both channels are spread breadth first, one voxel at a time, and removed by
+/// a second flood fill that clears everything lit by a removed source before
+/// spreading the light of the remaining sources back into the cleared area.
+/// Chunks that aren't loaded or generated yet are skipped; they pull in the
+/// light around them once they arrive, see [`light_new_chunk`].
+struct LightEngine<'a> {
+    chunk_map: &'a mut ChunkMap,
+    /// Chunks with a voxel whose light, or whose neighbor's light, changed.
+    changed: HashSet<IVec3>,
+    spread: [VecDeque<IVec3>; 2],
+    removal: [VecDeque<(IVec3, u8)>; 2],
+}
+
+impl<'a> LightEngine<'a> {
+    fn new(chunk_map: &'a mut ChunkMap) -> Self {
+        Self {
+            chunk_map,
+            changed: HashSet::new(),
+            spread: Default::default(),
+            removal: Default::default(),
+        }
+    }
+
+    fn chunk(&self, chunk_pos: IVec3) -> Option<&ChunkData> {
+        self.chunk_map
+            .get(&chunk_pos)
+            .filter(|chunk| chunk.generated)
+            .map(Arc::as_ref)
+    }
+
+    /// Voxel and light at `world_pos`, if its chunk is loaded.
+    fn cell(&self, world_pos: IVec3) -> Option<(Voxel, LightLevel)> {
+        let chunk = self.chunk(WorldManager::world_to_chunk_pos(&world_pos))?;
+        let local_pos = WorldManager::world_to_local_pos(&world_pos);
+        let (x, y, z) = (
+            local_pos.x as usize,
+            local_pos.y as usize,
+            local_pos.z as usize,
+        );
+
+        Some((chunk.get_voxel(x, y, z), chunk.get_light(x, y, z)))
+    }
+
+    fn set_light(&mut self, world_pos: IVec3, channel: LightChannel, level: u8) {
+        let chunk_pos = WorldManager::world_to_chunk_pos(&world_pos);
+        let local_pos = WorldManager::world_to_local_pos(&world_pos);
+
+        let Some(chunk) = self
+            .chunk_map
+            .get_mut(&chunk_pos)
+            .filter(|chunk| chunk.generated)
+        else {
+            return;
+        };
+
+        let (x, y, z) = (
+            local_pos.x as usize,
+            local_pos.y as usize,
+            local_pos.z as usize,
+        );
+        let light = chunk.get_light(x, y, z);
+        if light.get(channel) == level {
+            return;
+        }
+        Arc::make_mut(chunk).set_light(light.with(channel, level), x, y, z);
+
+        // Faces of neighboring chunks, diagonal ones included, are shaded by
+        // the voxels on our borders, edges and corners
+        let side = |axis: usize| match local_pos[axis] {
+            0 => -1,
+            position if position == SIZE as i32 - 1 => 1,
+            _ => 0,
+        };
+        let (sx, sy, sz) = (side(0), side(1), side(2));
+
+        for x in [0, sx] {
+            for y in [0, sy] {
+                for z in [0, sz] {
+                    self.changed.insert(chunk_pos + IVec3::new(x, y, z));
+                }
+            }
+        }
+    }
+
+    /// Lights a whole chunk with `light` at once, rather than voxel by voxel.
+    fn fill_light(&mut self, chunk_pos: IVec3, light: LightLevel) {
+        let Some(chunk) = self
+            .chunk_map
+            .get_mut(&chunk_pos)
+            .filter(|chunk| chunk.generated)
+        else {
+            return;
+        };
+
+        Arc::make_mut(chunk).fill_light(light);
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    self.changed.insert(chunk_pos + IVec3::new(x, y, z));
+                }
+            }
+        }
+    }
+
+    fn queue_spread(&mut self, world_pos: IVec3, channel: LightChannel) {
+        self.spread[channel as usize].push_back(world_pos);
+    }
+
+    fn queue_removal(&mut self, world_pos: IVec3, channel: LightChannel, level: u8) {
+        self.set_light(world_pos, channel, 0);
+        self.removal[channel as usize].push_back((world_pos, level));
+    }
+
+    /// Clears the light queued for removal, then spreads all queued light.
+    fn run(&mut self) {
+        for channel in LightChannel::ALL {
+            self.run_removal(channel);
+            self.run_spread(channel);
+        }
+    }
+
+    fn run_spread(&mut self, channel: LightChannel) {
+        while let Some(world_pos) = self.spread[channel as usize].pop_front() {
+            let Some((_, light)) = self.cell(world_pos) else {
+                continue;
+            };
+
+            let level = light.get(channel);
+            if level <= 1 {
+                continue;
+            }
+
+            for (face_direction, offset) in FaceDirection::neighbor_offsets() {
+                let neighbor = world_pos + offset;
+                let Some((voxel, neighbor_light)) = self.cell(neighbor) else {
+                    continue;
+                };
+
+                let spread = channel.spread(level, face_direction);
+                if !voxel.blocks_light() && neighbor_light.get(channel) < spread {
+                    self.set_light(neighbor, channel, spread);
+                    self.queue_spread(neighbor, channel);
+                }
+            }
+        }
+    }
+
+    fn run_removal(&mut self, channel: LightChannel) {
+        while let Some((world_pos, level)) = self.removal[channel as usize].pop_front() {
+            for (face_direction, offset) in FaceDirection::neighbor_offsets() {
+                let neighbor = world_pos + offset;
+                let Some((voxel, neighbor_light)) = self.cell(neighbor) else {
+                    continue;
+                };
+
+                let neighbor_level = neighbor_light.get(channel);
+                if neighbor_level == 0 {
+                    continue;
+                }
+
+                // Anything dimmer, or sky light falling straight down from the
+                // removed voxel, was lit by it. Anything else is lit by another
+                // source and has to spread back into the cleared area.
+                if neighbor_level < level
+                    || (channel.spread(level, face_direction) == level && neighbor_level == level)
+                {
+                    self.queue_removal(neighbor, channel, neighbor_level);
+
+                    let emission = voxel.light_emission();
+                    if channel == LightChannel::Block && emission > 0 {
+                        self.set_light(neighbor, channel, emission);
+                        self.queue_spread(neighbor, channel);
+                    }
+                } else {
+                    self.queue_spread(neighbor, channel);
+                }
+            }
+        }
+    }
+}
+
+/// Lights a chunk that was just inserted into `chunk_map`, exchanging light
+/// with its loaded neighbors.
+///
+/// Where the upper neighbor isn't loaded, a column is assumed to be under the
+/// open sky if the terrain surface of `terrain_generator` lies below the top
+/// of the chunk, and in the dark otherwise. Once that neighbor arrives, its
+/// actual light replaces the guess.
+///
+/// Returns the chunks that need to be re-meshed, including the new chunk.
+pub fn light_new_chunk(
+    chunk_map: &mut ChunkMap,
+    chunk_pos: IVec3,
+    terrain_generator: &TerrainGenerator,
+) -> HashSet<IVec3> {
+    let mut engine = LightEngine::new(chunk_map);
+    engine.changed.insert(chunk_pos);
+
+    let Some(chunk) = engine.chunk(chunk_pos) else {
+        return engine.changed;
+    };
+
+    let origin = chunk_pos * SIZE as i32;
+    let world_pos =
+        |x: usize, y: usize, z: usize| origin + IVec3::new(x as i32, y as i32, z as i32);
+
+    // Without the chunk above, only the columns whose surface is inside or
+    // below this chunk are open to the sky. Chunks entirely above or below
+    // the range the surface can reach don't need to look at every column.
+    let top = origin.y + SIZE as i32;
+    let (lowest_surface, highest_surface) = terrain_generator.surface_range();
+    let open_without_above = |x: usize, z: usize| {
+        if origin.y > highest_surface.floor() as i32 {
+            true
+        } else if top <= lowest_surface.floor() as i32 {
+            false
+        } else {
+            let world_pos = world_pos(x, 0, z);
+            terrain_generator.surface_height(world_pos.x, world_pos.z) < top
+        }
+    };
+
+    // Sky light falling through each column from the chunk above. `lowest_lit`
+    // holds the lowest y of each `x + z * SIZE` column that is open to the
+    // sky, or SIZE if none.
+    let above = engine.chunk(chunk_pos + IVec3::Y);
+    let mut lowest_lit = [SIZE; SIZE * SIZE];
+    for x in 0..SIZE {
+        for z in 0..SIZE {
+            let open = match above {
+                Some(above) => above.get_light(x, 0, z).sky() == LightLevel::MAX,
+                None => open_without_above(x, z),
+            };
+            if !open {
+                continue;
+            }
+
+            for y in (0..SIZE).rev() {
+                if chunk.get_voxel(x, y, z).blocks_light() {
+                    break;
+                }
+                lowest_lit[x + z * SIZE] = y;
+            }
+        }
+    }
+
+    let emitters: Vec<(IVec3, u8)> = if chunk
+        .voxels()
+        .palette()
+        .iter()
+        .any(|voxel| voxel.light_emission() > 0)
+    {
+        (0..SIZE)
+            .flat_map(|z| (0..SIZE).flat_map(move |y| (0..SIZE).map(move |x| (x, y, z))))
+            .filter_map(|(x, y, z)| {
+                let emission = chunk.get_voxel(x, y, z).light_emission();
+                (emission > 0).then(|| (world_pos(x, y, z), emission))
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    // Light of the neighbors flowing in through the shared borders
+    let mut incoming = Vec::new();
+    for (face_direction, offset) in FaceDirection::neighbor_offsets() {
+        if engine.chunk(chunk_pos + offset).is_none() {
+            continue;
+        }
+
+        let d = face_direction.axis();
+        let u = (d + 1) % 3;
+        let v = (d + 2) % 3;
+        let depth = if offset[d] > 0 { SIZE - 1 } else { 0 };
+
+        for j in 0..SIZE {
+            for i in 0..SIZE {
+                let mut local = [0; 3];
+                local[d] = depth;
+                local[u] = i;
+                local[v] = j;
+
+                if !chunk.get_voxel(local[0], local[1], local[2]).blocks_light() {
+                    incoming.push(world_pos(local[0], local[1], local[2]) + offset);
+                }
+            }
+        }
+    }
+
+    // Chunks open to the sky all the way down, like those high above the
+    // ground, are lit in one go and keep a single light level
+    if lowest_lit.iter().all(|&y| y == 0) {
+        engine.fill_light(chunk_pos, LightLevel::SKY);
+    }
+
+    for x in 0..SIZE {
+        for z in 0..SIZE {
+            for y in lowest_lit[x + z * SIZE]..SIZE {
+                let position = world_pos(x, y, z);
+                engine.set_light(position, LightChannel::Sky, LightLevel::MAX);
+
+                // Only voxels next to something darker have to spread their light
+                let on_border = x == 0 || z == 0 || x == SIZE - 1 || z == SIZE - 1 || y == 0;
+                let next_to_shadow =
+                    [(1, 0), (-1, 0), (0, 1), (0, -1)]
+                        .into_iter()
+                        .any(|(dx, dz)| {
+                            let nx = (x as i32 + dx) as usize;
+                            let nz = (z as i32 + dz) as usize;
+                            nx < SIZE && nz < SIZE && y < lowest_lit[nx + nz * SIZE]
+                        });
+
+                if on_border || next_to_shadow {
+                    engine.queue_spread(position, LightChannel::Sky);
+                }
+            }
+        }
+    }
+
+    for (position, emission) in emitters {
+        engine.set_light(position, LightChannel::Block, emission);
+        engine.queue_spread(position, LightChannel::Block);
+    }
+
+    for position in incoming {
+        engine.queue_spread(position, LightChannel::Sky);
+        engine.queue_spread(position, LightChannel::Block);
+    }
+
+    // The chunk below may have assumed open sky before this chunk was loaded
+    if let Some(below) = engine.chunk(chunk_pos - IVec3::Y) {
+        let blocked: Vec<IVec3> = (0..SIZE)
+            .flat_map(|x| (0..SIZE).map(move |z| (x, z)))
+            .filter(|&(x, z)| {
+                below.get_light(x, SIZE - 1, z).sky() == LightLevel::MAX
+                    && lowest_lit[x + z * SIZE] != 0
+            })
+            .map(|(x, z)| world_pos(x, 0, z) - IVec3::Y)
+            .collect();
+
+        for position in blocked {
+            engine.queue_removal(position, LightChannel::Sky, LightLevel::MAX);
+        }
+    }
+
+    engine.run();
+    engine.changed
+}
+
+/// Updates the light around voxels that were just changed in `chunk_map`.
+///
+/// Returns the chunks that need to be re-meshed because their light changed.
+pub fn update_light(
+    chunk_map: &mut ChunkMap,
+    edited: impl IntoIterator<Item = IVec3>,
+) -> HashSet<IVec3> {
+    let mut engine = LightEngine::new(chunk_map);
+    let edited: Vec<IVec3> = edited.into_iter().collect();
+
+    for &position in &edited {
+        let Some((_, light)) = engine.cell(position) else {
+            continue;
+        };
+
+        for channel in LightChannel::ALL {
+            let level = light.get(channel);
+            if level > 0 {
+                engine.queue_removal(position, channel, level);
+            }
+        }
+    }
+
+    for channel in LightChannel::ALL {
+        engine.run_removal(channel);
+    }
+
+    for &position in &edited {
+        let Some((voxel, _)) = engine.cell(position) else {
+            continue;
+        };
+
+        let emission = voxel.light_emission();
+        if emission > 0 {
+            engine.set_light(position, LightChannel::Block, emission);
+            engine.queue_spread(position, LightChannel::Block);
+        }
+
+        if voxel.blocks_light() {
+            continue;
+        }
+
+        // Let the surrounding light back into the voxel
+        for (_, offset) in FaceDirection::neighbor_offsets() {
+            let neighbor = position + offset;
+
+            if engine.cell(neighbor).is_some() {
+                engine.queue_spread(neighbor, LightChannel::Sky);
+                engine.queue_spread(neighbor, LightChannel::Block);
+            } else if offset == IVec3::Y {
+                engine.set_light(position, LightChannel::Sky, LightLevel::MAX);
+                engine.queue_spread(position, LightChannel::Sky);
+            }
+        }
+    }
+
+    engine.run();
+    engine.changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::TerrainSettings;
+
+    /// Generated chunk of `voxel`, with `carve` returning the voxel to put at
+    /// any local position instead, if any.
+    fn chunk(
+        position: IVec3,
+        voxel: Voxel,
+        carve: impl Fn(usize, usize, usize) -> Option<Voxel>,
+    ) -> ChunkData {
+        let mut chunk = ChunkData::new(position);
+        chunk.generated = true;
+        chunk.fill(voxel);
+
+        for z in 0..SIZE {
+            for y in 0..SIZE {
+                for x in 0..SIZE {
+                    if let Some(voxel) = carve(x, y, z) {
+                        chunk.set_voxel(voxel, x, y, z);
+                    }
+                }
+            }
+        }
+
+        chunk
+    }
+
+    fn insert(chunk_map: &mut ChunkMap, chunk: ChunkData) -> HashSet<IVec3> {
+        let position = chunk.position;
+        chunk_map.insert(position, Arc::new(chunk));
+        light_new_chunk(chunk_map, position, &TerrainGenerator::sunken())
+    }
+
+    fn light_at(chunk_map: &ChunkMap, world_pos: IVec3) -> LightLevel {
+        let chunk = &chunk_map[&WorldManager::world_to_chunk_pos(&world_pos)];
+        let local_pos = WorldManager::world_to_local_pos(&world_pos);
+
+        chunk.get_light(
+            local_pos.x as usize,
+            local_pos.y as usize,
+            local_pos.z as usize,
+        )
+    }
+
+    fn set_voxel(chunk_map: &mut ChunkMap, world_pos: IVec3, voxel: Voxel) -> HashSet<IVec3> {
+        let chunk = chunk_map
+            .get_mut(&WorldManager::world_to_chunk_pos(&world_pos))
+            .unwrap();
+        let local_pos = WorldManager::world_to_local_pos(&world_pos);
+        Arc::make_mut(chunk).set_voxel(
+            voxel,
+            local_pos.x as usize,
+            local_pos.y as usize,
+            local_pos.z as usize,
+        );
+
+        update_light(chunk_map, [world_pos])
+    }
+
+    #[test]
+    fn open_sky_chunks_keep_a_single_light_level() {
+        let mut chunk_map = ChunkMap::default();
+        let unlit = chunk(IVec3::Y, Voxel::Air, |_, _, _| None);
+        let voxels_size = unlit.heap_size();
+        insert(&mut chunk_map, unlit);
+
+        let chunk = &chunk_map[&IVec3::Y];
+        assert_eq!(chunk.get_light(7, 0, 20), LightLevel::SKY);
+        assert_eq!(chunk.heap_size(), voxels_size);
+    }
+
+    #[test]
+    fn chunks_below_the_surface_start_out_dark() {
+        // Flat surface at y = 40, in the middle of the chunk at y = 1
+        let terrain_generator = TerrainGenerator::new(
+            0,
+            TerrainSettings {
+                base_height: 40.0,
+                height_amplitude: 0.0,
+                ..TerrainSettings::default()
+            },
+        );
+        assert_eq!(terrain_generator.surface_height(5, 5), 40);
+
+        let mut chunk_map = ChunkMap::default();
+        let below = IVec3::ZERO;
+        chunk_map.insert(below, Arc::new(chunk(below, Voxel::Air, |_, _, _| None)));
+        light_new_chunk(&mut chunk_map, below, &terrain_generator);
+        assert_eq!(light_at(&chunk_map, IVec3::new(5, 31, 5)).sky(), 0);
+
+        // The surface chunk is open to the sky, which falls into the one below
+        let surface = IVec3::Y;
+        chunk_map.insert(
+            surface,
+            Arc::new(chunk(surface, Voxel::Air, |_, _, _| None)),
+        );
+        light_new_chunk(&mut chunk_map, surface, &terrain_generator);
+        for y in [63, 32, 31, 0] {
+            assert_eq!(
+                light_at(&chunk_map, IVec3::new(5, y, 5)).sky(),
+                LightLevel::MAX,
+                "at y = {y}"
+            );
+        }
+    }
+
+    #[test]
+    fn sky_light_falls_down_and_spreads_under_roofs() {
+        // Stone roof at the top with a hole in the middle
+        let mut chunk_map = ChunkMap::default();
+        let hole = |x: usize, z: usize| x == 16 && z == 16;
+        insert(
+            &mut chunk_map,
+            chunk(IVec3::ZERO, Voxel::Air, |x, y, z| {
+                (y == SIZE - 1 && !hole(x, z)).then_some(Voxel::Stone)
+            }),
+        );
+
+        // Straight down without fading, then falling off sideways
+        assert_eq!(
+            light_at(&chunk_map, IVec3::new(16, 0, 16)).sky(),
+            LightLevel::MAX
+        );
+        assert_eq!(
+            light_at(&chunk_map, IVec3::new(18, 5, 15)).sky(),
+            LightLevel::MAX - 3
+        );
+        assert_eq!(
+            light_at(&chunk_map, IVec3::new(0, 10, 16)),
+            LightLevel::default()
+        );
+
+        // Closing the hole darkens everything below the roof
+        set_voxel(&mut chunk_map, IVec3::new(16, 31, 16), Voxel::Stone);
+        for position in [
+            IVec3::new(16, 30, 16),
+            IVec3::new(16, 0, 16),
+            IVec3::new(18, 5, 15),
+        ] {
+            assert_eq!(light_at(&chunk_map, position), LightLevel::default());
+        }
+    }
+
+    #[test]
+    fn block_light_falls_off_with_distance() {
+        // Closed stone box with a glowstone in the middle of its hollow
+        let mut chunk_map = ChunkMap::default();
+        let source = IVec3::splat(16);
+        insert(
+            &mut chunk_map,
+            chunk(IVec3::ZERO, Voxel::Stone, |x, y, z| {
+                let local = IVec3::new(x as i32, y as i32, z as i32);
+                if local == source {
+                    Some(Voxel::Glowstone)
+                } else {
+                    (local.cmpge(IVec3::splat(1)).all() && local.cmple(IVec3::splat(30)).all())
+                        .then_some(Voxel::Air)
+                }
+            }),
+        );
+
+        for offset in [
+            IVec3::X,
+            IVec3::new(-3, 0, 2),
+            IVec3::new(4, 5, -5),
+            IVec3::splat(-14),
+        ] {
+            let distance = offset.abs().element_sum() as u8;
+            let light = light_at(&chunk_map, source + offset);
+
+            assert_eq!(light.block(), 15u8.saturating_sub(distance), "at {offset}");
+            assert_eq!(light.sky(), 0);
+        }
+
+        // Replacing the glowstone removes its light
+        set_voxel(&mut chunk_map, source, Voxel::Stone);
+        for offset in [IVec3::X, IVec3::new(-3, 0, 2)] {
+            assert_eq!(light_at(&chunk_map, source + offset), LightLevel::default());
+        }
+    }
+
+    #[test]
+    fn block_light_crosses_chunk_borders() {
+        // Tunnel along x through two stone chunks, with a glowstone near the
+        // end of the first one
+        let tunnel = |position: IVec3| {
+            chunk(position, Voxel::Stone, move |x, y, z| {
+                if y != 16 || z != 16 {
+                    None
+                } else if position == IVec3::ZERO && x == 28 {
+                    Some(Voxel::Glowstone)
+                } else {
+                    Some(Voxel::Air)
+                }
+            })
+        };
+
+        // Either chunk may be lit first
+        for order in [[IVec3::ZERO, IVec3::X], [IVec3::X, IVec3::ZERO]] {
+            let mut chunk_map = ChunkMap::default();
+            for position in order {
+                insert(&mut chunk_map, tunnel(position));
+            }
+
+            for x in [31, 32, 35, 42] {
+                let light = light_at(&chunk_map, IVec3::new(x, 16, 16));
+                assert_eq!(light.block(), 15 - (x - 28) as u8, "at x = {x}");
+            }
+
+            // Removing the source clears the light on both sides
+            let changed = set_voxel(&mut chunk_map, IVec3::new(28, 16, 16), Voxel::Air);
+            assert!(changed.contains(&IVec3::X));
+            for x in [27, 33] {
+                assert_eq!(
+                    light_at(&chunk_map, IVec3::new(x, 16, 16)),
+                    LightLevel::default()
+                );
+            }
+        }
+    }
+}
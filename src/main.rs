@@ -1,27 +1,54 @@
+pub mod binary_mesher;
 pub mod camera;
 pub mod chunk;
+pub mod chunk_queue;
 pub mod debug;
+pub mod features;
+pub mod interaction;
+pub mod light;
+pub mod manifest;
+pub mod mesher;
+pub mod ores;
+pub mod random;
+pub mod raycast;
+pub mod region;
 pub mod terrain;
 pub mod voxel;
+pub mod voxel_storage;
 pub mod world;
 
-use std::time::Instant;
-
 use bevy::prelude::*;
 
 use crate::{
-    camera::PlayerCameraPlugin, chunk::ChunkData, debug::DebugPlugin, voxel::Voxel,
-    world::WorldPlugin,
+    camera::PlayerCameraPlugin,
+    debug::DebugPlugin,
+    interaction::InteractionPlugin,
+    manifest::WorldManifest,
+    world::{WorldConfig, WorldPlugin},
 };
 
 fn main() {
+    // The manifest is opened here rather than left to `WorldPlugin` so a world
+    // that can't be opened is reported as an error instead of a panic
+    let (world_config, world_manifest) = WorldConfig::from_args(std::env::args().skip(1))
+        .and_then(|mut world_config| {
+            let world_manifest = WorldManifest::open_or_create(&mut world_config)?;
+            Ok((world_config, world_manifest))
+        })
+        .unwrap_or_else(|err| {
+            eprintln!("error: {err}");
+            std::process::exit(2);
+        });
+
     App::new()
         .add_plugins((DefaultPlugins,))
+        .insert_resource(world_config)
+        .insert_resource(world_manifest)
         .add_plugins(PlayerCameraPlugin)
         .add_plugins(WorldPlugin)
+        .add_plugins(InteractionPlugin)
         .add_plugins(DebugPlugin)
         .add_systems(Startup, setup_environment)
-        .add_systems(Startup, spawn_large_chunks)
         .run();
 }
 
@@ -34,38 +61,3 @@ fn setup_environment(mut commands: Commands) {
         Transform::from_xyz(4.0, 50.0, 4.0),
     ));
 }
-
-fn spawn_large_chunks(
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    mut world: ResMut<world::WorldManager>,
-) {
-    let start_time = Instant::now();
-
-    // let seed = 12345;
-    // let size = 10;
-    // world.generate_world(size, seed);
-    //
-    // for x in 0..size {
-    //     for z in 0..size {
-    //         let chunk_pos = IVec3::new(x, 0, z);
-    //
-    //         if let Some(chunk) = world.get_chunk(&chunk_pos) {
-    //             let mesh = chunk.generate_mesh(&world);
-    //
-    //             commands.spawn((
-    //                 Mesh3d(meshes.add(mesh)),
-    //                 MeshMaterial3d(materials.add(StandardMaterial {
-    //                     base_color: Color::WHITE,
-    //                     unlit: false, // Enable lighting for better visuals
-    //                     ..default()
-    //                 })),
-    //                 chunk.get_world_transform(),
-    //             ));
-    //         }
-    //     }
-    // }
-    //
-    // dbg!(start_time.elapsed() * 1000);
-}
@@ -0,0 +1,156 @@
+use std::{
+    fs, io,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    camera::PlayerCamera,
+    terrain::{TerrainGenerator, TerrainSettings},
+    world::WorldConfig,
+};
+
+/// File name of the manifest inside a world directory.
+pub const MANIFEST_FILE: &str = "world.toml";
+
+/// Version of the on-disk world layout, bumped whenever the manifest or the
+/// region files change in a way older builds can't read.
+pub const WORLD_FORMAT_VERSION: u32 = 1;
+
+/// Everything about a world besides its chunks, saved as `world.toml`.
+#[derive(Resource, Clone, Debug, Serialize, Deserialize)]
+pub struct WorldManifest {
+    pub format_version: u32,
+    pub seed: u32,
+    /// [`TerrainGenerator::VERSION`] the world was created with.
+    pub generator_version: u32,
+    /// Creation time in seconds since the unix epoch.
+    pub created: u64,
+    pub terrain: TerrainSettings,
+    /// Last camera state, missing until the world was closed once.
+    pub camera: Option<CameraState>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct CameraState {
+    pub position: [f32; 3],
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+impl WorldManifest {
+    /// Opens the world in `config.directory`, or creates it if it has no
+    /// manifest yet.
+    ///
+    /// Existing worlds keep the seed and terrain settings they were created
+    /// with, which are written back into `config`.
+    pub fn open_or_create(config: &mut WorldConfig) -> Result<Self, String> {
+        let path = config.directory.join(MANIFEST_FILE);
+
+        let manifest = match fs::read_to_string(&path) {
+            Ok(contents) => {
+                let manifest: Self = toml::from_str(&contents)
+                    .map_err(|err| format!("invalid world manifest `{}`: {err}", path.display()))?;
+
+                if manifest.format_version > WORLD_FORMAT_VERSION {
+                    return Err(format!(
+                        "world `{}` uses format version {}, but only up to {WORLD_FORMAT_VERSION} is supported",
+                        config.directory.display(),
+                        manifest.format_version
+                    ));
+                }
+
+                config.seed = manifest.seed;
+                config.terrain = manifest.terrain.clone();
+
+                manifest
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let created = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |duration| duration.as_secs());
+
+                let manifest = Self {
+                    format_version: WORLD_FORMAT_VERSION,
+                    seed: config.seed,
+                    generator_version: TerrainGenerator::VERSION,
+                    created,
+                    terrain: config.terrain.clone(),
+                    camera: None,
+                };
+
+                manifest
+                    .save(&config.directory)
+                    .map_err(|err| format!("failed to create world manifest: {err}"))?;
+
+                manifest
+            }
+            Err(err) => {
+                return Err(format!(
+                    "failed to read world manifest `{}`: {err}",
+                    path.display()
+                ));
+            }
+        };
+
+        Ok(manifest)
+    }
+
+    /// Writes the manifest into `directory`, creating it if needed.
+    pub fn save(&self, directory: &Path) -> io::Result<()> {
+        let contents = toml::to_string_pretty(self).map_err(io::Error::other)?;
+
+        fs::create_dir_all(directory)?;
+
+        // Write to a temporary file first so a crash never leaves a torn manifest
+        let path = directory.join(MANIFEST_FILE);
+        let temporary_path = path.with_extension("toml.tmp");
+        fs::write(&temporary_path, contents)?;
+        fs::rename(temporary_path, path)
+    }
+}
+
+/// Moves the camera back to where it was when the world was last closed.
+pub(crate) fn restore_camera(
+    manifest: Res<WorldManifest>,
+    mut cameras: Query<(&mut PlayerCamera, &mut Transform)>,
+) {
+    let Some(state) = manifest.camera else {
+        return;
+    };
+
+    for (mut camera, mut transform) in cameras.iter_mut() {
+        camera.yaw = state.yaw;
+        camera.pitch = state.pitch;
+
+        transform.translation = Vec3::from_array(state.position);
+        transform.rotation = camera.rotation();
+    }
+}
+
+pub(crate) fn save_manifest_on_exit(
+    mut exit_events: EventReader<AppExit>,
+    mut manifest: ResMut<WorldManifest>,
+    world_config: Res<WorldConfig>,
+    cameras: Query<(&PlayerCamera, &Transform)>,
+) {
+    if exit_events.read().last().is_none() {
+        return;
+    }
+
+    if let Ok((camera, transform)) = cameras.single() {
+        manifest.camera = Some(CameraState {
+            position: transform.translation.to_array(),
+            yaw: camera.yaw,
+            pitch: camera.pitch,
+        });
+    }
+
+    match manifest.save(&world_config.directory) {
+        Ok(()) => info!("Saved world to {}", world_config.directory.display()),
+        Err(err) => error!("Failed to save world manifest: {err}"),
+    }
+}
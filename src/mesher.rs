@@ -0,0 +1,574 @@
+use bevy::{asset::RenderAssetUsages, prelude::*, render::mesh::Indices};
+
+use crate::{
+    chunk::{ChunkData, FACE_INDICES, FLIPPED_FACE_INDICES, FaceDirection},
+    light::LightLevel,
+    voxel::{RenderClass, Voxel},
+    world::ChunkMap,
+};
+
+/// Strategy used to turn chunk voxels into triangles.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MeshingAlgorithm {
+    /// One quad per exposed voxel face.
+    FaceCulling,
+    /// Exposed faces of the same voxel type are merged into maximal rectangles.
+    Greedy,
+    /// Greedy meshing driven by per-column bitmasks, see `binary_mesher`.
+    #[default]
+    BinaryGreedy,
+}
+
+impl MeshingAlgorithm {
+    pub const ALL: [MeshingAlgorithm; 3] = [
+        MeshingAlgorithm::FaceCulling,
+        MeshingAlgorithm::Greedy,
+        MeshingAlgorithm::BinaryGreedy,
+    ];
+
+    pub fn next(&self) -> Self {
+        match self {
+            MeshingAlgorithm::FaceCulling => MeshingAlgorithm::Greedy,
+            MeshingAlgorithm::Greedy => MeshingAlgorithm::BinaryGreedy,
+            MeshingAlgorithm::BinaryGreedy => MeshingAlgorithm::FaceCulling,
+        }
+    }
+}
+
+/// Brightness applied to a vertex for each ambient occlusion level, where 0 is
+/// a fully enclosed corner and 3 is an unobstructed one.
+const AO_CURVE: [f32; 4] = [0.45, 0.65, 0.82, 1.0];
+
+/// Ambient occlusion level of a vertex from its two side neighbors and the
+/// corner between them.
+fn vertex_ao(side1: bool, side2: bool, corner: bool) -> u8 {
+    if side1 && side2 {
+        0
+    } else {
+        3 - (side1 as u8 + side2 as u8 + corner as u8)
+    }
+}
+
+impl FaceDirection {
+    /// Ambient occlusion levels of the four vertices of a face pointing this
+    /// way, in the order of [`FaceDirection::face`].
+    ///
+    /// For each vertex the two voxels sharing an edge with it and the voxel on
+    /// the diagonal are sampled in the layer in front of the face.
+    /// `is_opaque` is asked about each of them by its offset from the voxel
+    /// right in front of the face.
+    pub(crate) fn ambient_occlusion(&self, is_opaque: impl Fn(IVec3) -> bool) -> [u8; 4] {
+        let d = self.axis();
+        let u = (d + 1) % 3;
+        let v = (d + 2) % 3;
+
+        self.face().map(|vertex| {
+            let mut side_u = IVec3::ZERO;
+            side_u[u] = if vertex[u] > 0.5 { 1 } else { -1 };
+
+            let mut side_v = IVec3::ZERO;
+            side_v[v] = if vertex[v] > 0.5 { 1 } else { -1 };
+
+            vertex_ao(
+                is_opaque(side_u),
+                is_opaque(side_v),
+                is_opaque(side_u + side_v),
+            )
+        })
+    }
+}
+
+/// Accumulates quads and turns them into a bevy [`Mesh`].
+#[derive(Default)]
+pub struct MeshBuilder {
+    vertices: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    colors: Vec<[f32; 4]>,
+    indices: Vec<u32>,
+}
+
+impl MeshBuilder {
+    /// Adds a quad for `face_direction` at `position`, stretched by `size`.
+    ///
+    /// `size` is the extent of the quad along each axis; the component along
+    /// the face axis only selects which side of the voxel the quad sits on and
+    /// should be 1. `ao` holds the ambient occlusion level of each vertex, in
+    /// the order of [`FaceDirection::face`], and `light` the light in front of
+    /// the face.
+    pub fn add_quad(
+        &mut self,
+        position: Vec3,
+        size: Vec3,
+        face_direction: FaceDirection,
+        voxel: Voxel,
+        ao: [u8; 4],
+        light: LightLevel,
+    ) {
+        let vertex_count = self.vertices.len() as u32;
+
+        let material = voxel.get_material();
+        let [r, g, b, a] = material.color.to_srgba().to_f32_array();
+        let normal = face_direction.normal();
+        let emissive = material.emission > 0;
+        let light = light.brightness();
+
+        for (vertex, ao) in face_direction.face().iter().zip(ao) {
+            self.vertices.push([
+                position.x + vertex[0] * size.x,
+                position.y + vertex[1] * size.y,
+                position.z + vertex[2] * size.z,
+            ]);
+
+            self.normals.push(normal);
+
+            // Light sources are drawn at full brightness, unaffected by their surroundings
+            let brightness = if emissive {
+                1.0
+            } else {
+                AO_CURVE[ao as usize] * light
+            };
+            self.colors
+                .push([r * brightness, g * brightness, b * brightness, a]);
+        }
+
+        // Split the quad along the brighter diagonal so occlusion is
+        // interpolated the same way regardless of the face orientation.
+        let face_indices = if ao[0] + ao[2] > ao[1] + ao[3] {
+            FLIPPED_FACE_INDICES
+        } else {
+            FACE_INDICES
+        };
+
+        for &index in face_indices.iter() {
+            self.indices.push(index + vertex_count);
+        }
+    }
+
+    pub fn build(self) -> Mesh {
+        let mut mesh = Mesh::new(
+            bevy::render::mesh::PrimitiveTopology::TriangleList,
+            RenderAssetUsages::MAIN_WORLD | RenderAssetUsages::RENDER_WORLD,
+        );
+
+        // Set vertex locations in the world
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.vertices);
+
+        // Set face normals, every quad is axis aligned so no need to compute them
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+
+        // Set colors
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, self.colors);
+
+        // Set the triangle indices
+        mesh.insert_indices(Indices::U32(self.indices));
+
+        mesh
+    }
+}
+
+/// Meshes of a chunk, one per [`RenderClass`].
+pub struct ChunkMesh {
+    pub opaque: Mesh,
+    pub cutout: Mesh,
+    pub translucent: Mesh,
+}
+
+impl ChunkMesh {
+    pub fn get(&self, render_class: RenderClass) -> &Mesh {
+        match render_class {
+            RenderClass::Opaque => &self.opaque,
+            RenderClass::Cutout => &self.cutout,
+            RenderClass::Translucent => &self.translucent,
+        }
+    }
+
+    /// The mesh of every render class that has any faces.
+    pub fn into_meshes(self) -> impl Iterator<Item = (RenderClass, Mesh)> {
+        [
+            (RenderClass::Opaque, self.opaque),
+            (RenderClass::Cutout, self.cutout),
+            (RenderClass::Translucent, self.translucent),
+        ]
+        .into_iter()
+        .filter(|(_, mesh)| mesh.count_vertices() > 0)
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        RenderClass::ALL
+            .iter()
+            .map(|render_class| self.get(*render_class).count_vertices())
+            .sum()
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        RenderClass::ALL
+            .iter()
+            .map(|render_class| {
+                self.get(*render_class)
+                    .indices()
+                    .map_or(0, |indices| indices.len() / 3)
+            })
+            .sum()
+    }
+}
+
+/// Receives the quads a meshing algorithm produces.
+pub trait QuadSink {
+    /// See [`MeshBuilder::add_quad`].
+    fn add_quad(
+        &mut self,
+        position: Vec3,
+        size: Vec3,
+        face_direction: FaceDirection,
+        voxel: Voxel,
+        ao: [u8; 4],
+        light: LightLevel,
+    );
+}
+
+/// Sorts quads into the mesh of their voxel's [`RenderClass`].
+#[derive(Default)]
+pub struct ChunkMeshBuilder {
+    opaque: MeshBuilder,
+    cutout: MeshBuilder,
+    translucent: MeshBuilder,
+}
+
+impl ChunkMeshBuilder {
+    pub fn build(self) -> ChunkMesh {
+        ChunkMesh {
+            opaque: self.opaque.build(),
+            cutout: self.cutout.build(),
+            translucent: self.translucent.build(),
+        }
+    }
+}
+
+impl QuadSink for ChunkMeshBuilder {
+    fn add_quad(
+        &mut self,
+        position: Vec3,
+        size: Vec3,
+        face_direction: FaceDirection,
+        voxel: Voxel,
+        ao: [u8; 4],
+        light: LightLevel,
+    ) {
+        let builder = match voxel.render_class() {
+            RenderClass::Opaque => &mut self.opaque,
+            RenderClass::Cutout => &mut self.cutout,
+            RenderClass::Translucent => &mut self.translucent,
+        };
+
+        builder.add_quad(position, size, face_direction, voxel, ao, light);
+    }
+}
+
+impl ChunkData {
+    /// Ambient occlusion levels of the four vertices of a voxel face, see
+    /// [`FaceDirection::ambient_occlusion`]. The voxels sampled may lie in a
+    /// neighboring chunk.
+    pub(crate) fn face_ambient_occlusion(
+        &self,
+        position: IVec3,
+        face_direction: FaceDirection,
+        chunk_map: &ChunkMap,
+    ) -> [u8; 4] {
+        let front = position + face_direction.offset();
+
+        face_direction.ambient_occlusion(|offset| {
+            let sample = front + offset;
+            self.get_neighbor_voxel(sample.x, sample.y, sample.z, chunk_map)
+                .is_opaque()
+        })
+    }
+
+    /// Builds meshes where coplanar faces of the same voxel type are merged.
+    ///
+    /// Every slice of the chunk along each face axis is turned into a mask of
+    /// visible faces, which is then swept row by row, growing each quad first
+    /// along `u` and then along `v` as long as the mask keeps matching. Faces
+    /// only merge when their voxel type, ambient occlusion and light are
+    /// identical.
+    pub(crate) fn generate_greedy_mesh(&self, chunk_map: &ChunkMap, builder: &mut impl QuadSink) {
+        const SIZE: usize = ChunkData::SIZE;
+
+        let mut mask: [Option<(Voxel, [u8; 4], LightLevel)>; SIZE * SIZE] = [None; SIZE * SIZE];
+
+        for (face_direction, offset) in FaceDirection::neighbor_offsets() {
+            let d = face_direction.axis();
+            let u = (d + 1) % 3;
+            let v = (d + 2) % 3;
+
+            for slice in 0..SIZE {
+                for j in 0..SIZE {
+                    for i in 0..SIZE {
+                        let mut position = IVec3::ZERO;
+                        position[d] = slice as i32;
+                        position[u] = i as i32;
+                        position[v] = j as i32;
+
+                        let voxel = self.get_voxel(
+                            position.x as usize,
+                            position.y as usize,
+                            position.z as usize,
+                        );
+                        let neighbor = position + offset;
+
+                        mask[i + j * SIZE] =
+                            voxel
+                                .is_face_visible(self.get_neighbor_voxel(
+                                    neighbor.x, neighbor.y, neighbor.z, chunk_map,
+                                ))
+                                .then(|| {
+                                    let ao = self.face_ambient_occlusion(
+                                        position,
+                                        face_direction,
+                                        chunk_map,
+                                    );
+                                    let light = self.get_neighbor_light(
+                                        neighbor.x, neighbor.y, neighbor.z, chunk_map,
+                                    );
+                                    (voxel, ao, light)
+                                });
+                    }
+                }
+
+                for j in 0..SIZE {
+                    let mut i = 0;
+                    while i < SIZE {
+                        let Some(face) = mask[i + j * SIZE] else {
+                            i += 1;
+                            continue;
+                        };
+
+                        let mut width = 1;
+                        while i + width < SIZE && mask[i + width + j * SIZE] == Some(face) {
+                            width += 1;
+                        }
+
+                        let mut height = 1;
+                        'grow: while j + height < SIZE {
+                            for k in 0..width {
+                                if mask[i + k + (j + height) * SIZE] != Some(face) {
+                                    break 'grow;
+                                }
+                            }
+                            height += 1;
+                        }
+
+                        for row in j..j + height {
+                            mask[i + row * SIZE..i + width + row * SIZE].fill(None);
+                        }
+
+                        let mut position = Vec3::ZERO;
+                        position[d] = slice as f32;
+                        position[u] = i as f32;
+                        position[v] = j as f32;
+
+                        let mut size = Vec3::ONE;
+                        size[u] = width as f32;
+                        size[v] = height as f32;
+
+                        let (voxel, ao, light) = face;
+                        builder.add_quad(position, size, face_direction, voxel, ao, light);
+
+                        i += width;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bevy::platform::collections::{HashMap, HashSet};
+
+    use super::*;
+    use crate::{light, random::SplitMix64, terrain::TerrainGenerator};
+
+    /// Total face area per direction and voxel.
+    #[derive(Default)]
+    struct FaceArea(HashMap<(FaceDirection, Voxel), u32>);
+
+    impl QuadSink for FaceArea {
+        fn add_quad(
+            &mut self,
+            _position: Vec3,
+            size: Vec3,
+            face_direction: FaceDirection,
+            voxel: Voxel,
+            _ao: [u8; 4],
+            _light: LightLevel,
+        ) {
+            let d = face_direction.axis();
+            let area = size[(d + 1) % 3] * size[(d + 2) % 3];
+            *self.0.entry((face_direction, voxel)).or_default() += area as u32;
+        }
+    }
+
+    /// Chunk at `position` with uneven terrain, a lake, scattered leaves and
+    /// light sources, so every kind of face and ambient occlusion shows up.
+    fn mixed_chunk(position: IVec3, seed: u64) -> ChunkData {
+        let mut random = SplitMix64::new(seed);
+        let mut chunk = ChunkData::new(position);
+        chunk.generated = true;
+
+        for x in 0..ChunkData::SIZE {
+            for z in 0..ChunkData::SIZE {
+                let height = 4 + (x * 3 + z * 5) % 11 + random.range(0, 3) as usize;
+
+                for y in 0..ChunkData::SIZE {
+                    let voxel = if y < height {
+                        match random.range(0, 20) {
+                            0 => Voxel::Air,
+                            1 => Voxel::Glowstone,
+                            2..=4 => Voxel::Dirt,
+                            _ => Voxel::Stone,
+                        }
+                    } else if y < 10 {
+                        Voxel::Water
+                    } else if random.chance(0.05) {
+                        Voxel::Leaves
+                    } else {
+                        Voxel::Air
+                    };
+
+                    chunk.set_voxel(voxel, x, y, z);
+                }
+            }
+        }
+
+        chunk
+    }
+
+    #[test]
+    fn algorithms_cover_the_same_faces() {
+        let chunk = mixed_chunk(IVec3::ZERO, 1);
+
+        // Faces along the borders are culled against loaded neighbors and
+        // shown towards missing ones.
+        let mut chunk_map = ChunkMap::default();
+        for position in [IVec3::X, IVec3::NEG_Y, IVec3::Z] {
+            chunk_map.insert(position, Arc::new(mixed_chunk(position, 2)));
+        }
+
+        let areas = MeshingAlgorithm::ALL.map(|algorithm| {
+            let mut area = FaceArea::default();
+            chunk.generate_quads(&chunk_map, algorithm, &mut area);
+            area.0
+        });
+
+        for face_direction in [FaceDirection::PosX, FaceDirection::NegY] {
+            for voxel in [Voxel::Stone, Voxel::Water, Voxel::Leaves] {
+                assert!(areas[0].contains_key(&(face_direction, voxel)));
+            }
+        }
+
+        for (algorithm, area) in MeshingAlgorithm::ALL.iter().zip(&areas).skip(1) {
+            for (key, expected) in &areas[0] {
+                assert_eq!(area.get(key), Some(expected), "{algorithm:?}: {key:?}");
+            }
+            assert_eq!(area.len(), areas[0].len(), "{algorithm:?}");
+        }
+    }
+
+    /// Every quad with the voxel, ambient occlusion and light it is drawn with.
+    #[derive(Default)]
+    struct Quads(HashSet<(IVec3, IVec3, FaceDirection, Voxel, [u8; 4], LightLevel)>);
+
+    impl QuadSink for Quads {
+        fn add_quad(
+            &mut self,
+            position: Vec3,
+            size: Vec3,
+            face_direction: FaceDirection,
+            voxel: Voxel,
+            ao: [u8; 4],
+            light: LightLevel,
+        ) {
+            let quad = (
+                position.as_ivec3(),
+                size.as_ivec3(),
+                face_direction,
+                voxel,
+                ao,
+                light,
+            );
+            assert!(self.0.insert(quad), "{quad:?} emitted twice");
+        }
+    }
+
+    #[test]
+    fn binary_mesher_matches_greedy_mesher() {
+        let mut chunk_map = ChunkMap::default();
+        for (position, seed) in [(IVec3::ZERO, 3), (IVec3::Y, 4), (IVec3::NEG_Z, 5)] {
+            chunk_map.insert(position, Arc::new(mixed_chunk(position, seed)));
+            light::light_new_chunk(&mut chunk_map, position, &TerrainGenerator::sunken());
+        }
+
+        let [greedy, binary] =
+            [MeshingAlgorithm::Greedy, MeshingAlgorithm::BinaryGreedy].map(|algorithm| {
+                let mut quads = Quads::default();
+                chunk_map[&IVec3::ZERO].generate_quads(&chunk_map, algorithm, &mut quads);
+                quads.0
+            });
+
+        assert!(!greedy.is_empty());
+        assert_eq!(greedy, binary);
+    }
+
+    #[test]
+    fn normals_are_axis_aligned() {
+        let mut chunk = ChunkData::new(IVec3::ZERO);
+        for x in 0..ChunkData::SIZE {
+            for z in 0..ChunkData::SIZE {
+                // Uneven steps so every algorithm produces faces on all six sides
+                // with a mix of ambient occlusion levels.
+                for y in 0..(x + z) % 5 + 1 {
+                    chunk.set_voxel(Voxel::Stone, x, y, z);
+                }
+            }
+        }
+
+        let chunk_map = ChunkMap::default();
+
+        for algorithm in MeshingAlgorithm::ALL {
+            let mesh = chunk.generate_mesh(&chunk_map, algorithm).opaque;
+
+            let positions = mesh
+                .attribute(Mesh::ATTRIBUTE_POSITION)
+                .and_then(|values| values.as_float3())
+                .expect("mesh has positions");
+            let normals = mesh
+                .attribute(Mesh::ATTRIBUTE_NORMAL)
+                .and_then(|values| values.as_float3())
+                .expect("mesh has normals");
+
+            assert_eq!(positions.len(), normals.len());
+            assert!(!normals.is_empty());
+
+            for normal in normals {
+                let non_zero = normal.iter().filter(|c| **c != 0.0).count();
+                assert_eq!(non_zero, 1, "{algorithm:?}: {normal:?} is not axis aligned");
+                assert_eq!(Vec3::from(*normal).length(), 1.0);
+            }
+
+            // Every triangle must face the same way as the normals of its vertices.
+            let Some(Indices::U32(indices)) = mesh.indices() else {
+                panic!("mesh has u32 indices");
+            };
+            for triangle in indices.chunks(3) {
+                let [a, b, c] = [0, 1, 2].map(|i| Vec3::from(positions[triangle[i] as usize]));
+                let face_normal = (b - a).cross(c - a).normalize();
+
+                for &index in triangle {
+                    assert_eq!(face_normal, Vec3::from(normals[index as usize]));
+                }
+            }
+        }
+    }
+}
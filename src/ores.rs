@@ -0,0 +1,188 @@
+use bevy::math::IVec3;
+
+use crate::{chunk::ChunkData, random::SplitMix64, terrain::TerrainGenerator, voxel::Voxel};
+
+/// How an ore is spread through the stone.
+pub struct OreDefinition {
+    pub voxel: Voxel,
+    /// Heights veins may start at, inclusive. Veins are most common in the
+    /// middle of the range and get rarer towards both ends, see
+    /// [`Self::density`].
+    pub min_y: i32,
+    pub max_y: i32,
+    /// Number of voxels in a vein, which also bounds how far it can reach from
+    /// where it starts.
+    pub vein_size: i32,
+    /// Veins started per chunk sized cell of the height range, on average.
+    pub veins_per_chunk: u32,
+}
+
+impl OreDefinition {
+    /// Chance of a vein starting at height `y`, 1 in the middle of the height
+    /// range and falling off linearly to 0 just beyond its ends.
+    ///
+    /// Averaged over the range that is about a half, so twice
+    /// `veins_per_chunk` starts are tried per cell.
+    pub fn density(&self, y: i32) -> f64 {
+        let center = (self.min_y + self.max_y) as f64 / 2.0;
+        let half_range = (self.max_y - self.min_y) as f64 / 2.0 + 1.0;
+
+        (1.0 - (y as f64 - center).abs() / half_range).max(0.0)
+    }
+}
+
+/// Every ore, from most to least common.
+pub const ORES: [OreDefinition; 4] = [
+    OreDefinition {
+        voxel: Voxel::CoalOre,
+        min_y: -80,
+        max_y: 10,
+        vein_size: 14,
+        veins_per_chunk: 10,
+    },
+    OreDefinition {
+        voxel: Voxel::IronOre,
+        min_y: -112,
+        max_y: -8,
+        vein_size: 8,
+        veins_per_chunk: 6,
+    },
+    OreDefinition {
+        voxel: Voxel::GoldOre,
+        min_y: -160,
+        max_y: -48,
+        vein_size: 6,
+        veins_per_chunk: 2,
+    },
+    OreDefinition {
+        voxel: Voxel::DiamondOre,
+        min_y: -224,
+        max_y: -96,
+        vein_size: 4,
+        veins_per_chunk: 1,
+    },
+];
+
+impl TerrainGenerator {
+    /// Replaces stone in `chunk_data` with ore veins.
+    ///
+    /// Veins are laid out per chunk sized cell, from a generator seeded with
+    /// the world seed and the cell position, as random walks from where they
+    /// start. Since no vein reaches further than one cell, every chunk replays
+    /// the veins of its own and its 26 neighboring cells and keeps the voxels
+    /// that land inside it, so veins crossing a chunk border match on both
+    /// sides however the chunks are loaded.
+    pub fn place_ores(&self, chunk_data: &mut ChunkData) {
+        if chunk_data.uniform_voxel() == Some(Voxel::Air) {
+            return;
+        }
+
+        const SIZE: i32 = ChunkData::SIZE as i32;
+        let origin = chunk_data.position * SIZE;
+
+        for (salt, ore) in ORES.iter().enumerate() {
+            for x in -1..=1 {
+                for y in -1..=1 {
+                    for z in -1..=1 {
+                        let cell = chunk_data.position + IVec3::new(x, y, z);
+                        let cell_origin = cell * SIZE;
+
+                        if ore.min_y > cell_origin.y + SIZE - 1 || ore.max_y < cell_origin.y {
+                            continue;
+                        }
+
+                        let mut random = SplitMix64::at(self.seed(), salt as u64, cell);
+
+                        for _ in 0..ore.veins_per_chunk * 2 {
+                            let mut position = IVec3::new(
+                                cell_origin.x + random.range(0, SIZE),
+                                cell_origin.y + random.range(0, SIZE),
+                                cell_origin.z + random.range(0, SIZE),
+                            );
+
+                            // Starts are spread over the whole cell rather
+                            // than the part of it inside the height range, so
+                            // cells only partly inside don't get denser veins
+                            if !random.chance(ore.density(position.y)) {
+                                continue;
+                            }
+
+                            for _ in 0..ore.vein_size {
+                                let local = position - origin;
+                                if local.cmpge(IVec3::ZERO).all()
+                                    && local.cmplt(IVec3::splat(SIZE)).all()
+                                {
+                                    let (lx, ly, lz) =
+                                        (local.x as usize, local.y as usize, local.z as usize);
+                                    if chunk_data.get_voxel(lx, ly, lz) == Voxel::Stone {
+                                        chunk_data.set_voxel(ore.voxel, lx, ly, lz);
+                                    }
+                                }
+
+                                let axis = random.range(0, 3) as usize;
+                                position[axis] += if random.chance(0.5) { 1 } else { -1 };
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::TerrainSettings;
+
+    #[test]
+    fn ore_is_densest_in_the_middle_of_its_height_range() {
+        const SIZE: i32 = ChunkData::SIZE as i32;
+        let coal = &ORES[0];
+
+        // Ore voxels by height in a few columns of stone chunks
+        let mut counts = vec![0; (5 * SIZE) as usize];
+        let bottom = -4 * SIZE;
+
+        for seed in 0..8 {
+            let terrain_generator = TerrainGenerator::new(seed, TerrainSettings::default());
+
+            for x in 0..4 {
+                for z in 0..4 {
+                    for y in -4..=0 {
+                        let mut chunk_data = ChunkData::new(IVec3::new(x, y, z));
+                        chunk_data.fill(Voxel::Stone);
+                        terrain_generator.place_ores(&mut chunk_data);
+
+                        for ly in 0..ChunkData::SIZE {
+                            for lz in 0..ChunkData::SIZE {
+                                for lx in 0..ChunkData::SIZE {
+                                    if chunk_data.get_voxel(lx, ly, lz) == coal.voxel {
+                                        counts[(y * SIZE + ly as i32 - bottom) as usize] += 1;
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let count = |min_y: i32, max_y: i32| -> u32 {
+            (min_y..=max_y).map(|y| counts[(y - bottom) as usize]).sum()
+        };
+
+        // Thirds of the range, and beyond the reach of any vein
+        let third = (coal.max_y - coal.min_y + 1) / 3;
+        let lower = count(coal.min_y, coal.min_y + third - 1);
+        let middle = count(coal.min_y + third, coal.max_y - third);
+        let upper = count(coal.max_y - third + 1, coal.max_y);
+
+        assert!(
+            middle > lower * 2 && middle > upper * 2,
+            "{lower} {middle} {upper}"
+        );
+        assert_eq!(count(bottom, coal.min_y - coal.vein_size), 0);
+        assert_eq!(count(coal.max_y + coal.vein_size, SIZE - 1), 0);
+    }
+}
@@ -0,0 +1,51 @@
+use bevy::math::IVec3;
+
+/// Small, fast pseudo random generator (SplitMix64) for world generation.
+///
+/// Unlike a thread or OS seeded generator, the sequence only depends on the
+/// seed, so features seeded from a world position come out the same every
+/// time, in whatever order chunks are generated.
+#[derive(Clone, Debug)]
+pub struct SplitMix64(u64);
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    /// Generator for the feature identified by `salt` at `position`.
+    pub fn at(seed: u32, salt: u64, position: IVec3) -> Self {
+        let mut hash = Self::new(seed as u64 ^ salt.rotate_left(32));
+        for coordinate in position.to_array() {
+            hash.0 ^= coordinate as u32 as u64;
+            hash.next_u64();
+        }
+
+        Self::new(hash.next_u64())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniformly distributed value in `0.0..1.0`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Value in `min..max`, `max` excluded.
+    pub fn range(&mut self, min: i32, max: i32) -> i32 {
+        debug_assert!(min < max);
+        min + (self.next_u64() % (max - min) as u64) as i32
+    }
+
+    /// Returns `true` with the given probability.
+    pub fn chance(&mut self, probability: f64) -> bool {
+        self.next_f64() < probability
+    }
+}
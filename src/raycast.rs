@@ -0,0 +1,250 @@
+use bevy::prelude::*;
+
+use crate::{chunk::FaceDirection, voxel::Voxel, world::WorldManager};
+
+/// How a ray treats voxels in chunks that aren't loaded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UnloadedChunks {
+    /// Pass through them as if they were air.
+    #[default]
+    PassThrough,
+    /// Stop the ray without reporting a hit.
+    Block,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RaycastHit {
+    /// World position of the voxel that was hit.
+    pub position: IVec3,
+    /// Face of the voxel the ray entered through.
+    pub face: FaceDirection,
+    /// Distance travelled along the ray until the hit.
+    pub distance: f32,
+    pub voxel: Voxel,
+}
+
+impl WorldManager {
+    /// Casts a ray through the loaded world and returns the first solid voxel it
+    /// hits within `max_distance`.
+    ///
+    /// Voxels are traversed with the Amanatides-Woo DDA, visiting every voxel
+    /// the ray passes through exactly once, in order, across chunk borders.
+    pub fn raycast(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        max_distance: f32,
+        unloaded: UnloadedChunks,
+    ) -> Option<RaycastHit> {
+        let direction = direction.normalize_or_zero();
+        if direction == Vec3::ZERO {
+            return None;
+        }
+
+        let mut position = origin.floor().as_ivec3();
+        let step = IVec3::from_array(direction.to_array().map(|d| d.signum() as i32));
+
+        // Distance along the ray between two voxel boundaries on each axis
+        let t_delta = direction.recip().abs();
+
+        // Distance along the ray to the first voxel boundary on each axis
+        let mut t_max = Vec3::from_array(std::array::from_fn(|axis| {
+            if direction[axis] > 0.0 {
+                (position[axis] as f32 + 1.0 - origin[axis]) * t_delta[axis]
+            } else if direction[axis] < 0.0 {
+                (origin[axis] - position[axis] as f32) * t_delta[axis]
+            } else {
+                f32::INFINITY
+            }
+        }));
+
+        // Starting inside a voxel counts as entering it against the ray
+        let dominant_axis = min_axis(-direction.abs());
+        let mut face = FaceDirection::from_axis(dominant_axis, direction[dominant_axis] < 0.0);
+        let mut distance = 0.0;
+
+        loop {
+            let chunk_pos = Self::world_to_chunk_pos(&position);
+            let local_pos = Self::world_to_local_pos(&position);
+
+            match self.get_chunk(&chunk_pos).filter(|chunk| chunk.generated) {
+                Some(chunk) => {
+                    let voxel = chunk.get_voxel(
+                        local_pos.x as usize,
+                        local_pos.y as usize,
+                        local_pos.z as usize,
+                    );
+
+                    if voxel.is_solid() {
+                        return Some(RaycastHit {
+                            position,
+                            face,
+                            distance,
+                            voxel,
+                        });
+                    }
+                }
+                None if unloaded == UnloadedChunks::Block => return None,
+                None => {}
+            }
+
+            let axis = min_axis(t_max);
+
+            distance = t_max[axis];
+            if distance > max_distance {
+                return None;
+            }
+
+            position[axis] += step[axis];
+            t_max[axis] += t_delta[axis];
+            face = FaceDirection::from_axis(axis, step[axis] < 0);
+        }
+    }
+}
+
+/// Index of the smallest component of `v`.
+fn min_axis(v: Vec3) -> usize {
+    if v.x <= v.y && v.x <= v.z {
+        0
+    } else if v.y <= v.z {
+        1
+    } else {
+        2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chunk::ChunkData, terrain::TerrainGenerator};
+
+    /// A world of empty generated chunks at `chunks` with stone at `stone`.
+    fn world(chunks: &[IVec3], stone: &[IVec3]) -> WorldManager {
+        let mut world_manager = WorldManager::default();
+        for &chunk_pos in chunks {
+            let mut chunk_data = ChunkData::new(chunk_pos);
+            chunk_data.generated = true;
+            for &position in stone {
+                if WorldManager::world_to_chunk_pos(&position) == chunk_pos {
+                    let local_pos = WorldManager::world_to_local_pos(&position).as_uvec3();
+                    chunk_data.set_voxel(
+                        Voxel::Stone,
+                        local_pos.x as usize,
+                        local_pos.y as usize,
+                        local_pos.z as usize,
+                    );
+                }
+            }
+            world_manager.insert_generated(chunk_data, &TerrainGenerator::sunken());
+        }
+        world_manager
+    }
+
+    fn assert_hit(hit: Option<RaycastHit>, position: IVec3, face: FaceDirection, distance: f32) {
+        let hit = hit.expect("ray should hit");
+        assert_eq!(hit.position, position);
+        assert_eq!(hit.face, face);
+        assert!(
+            (hit.distance - distance).abs() < 1e-4,
+            "{} != {distance}",
+            hit.distance
+        );
+        assert_eq!(hit.voxel, Voxel::Stone);
+    }
+
+    #[test]
+    fn rays_starting_inside_a_voxel_hit_it_immediately() {
+        let world_manager = world(&[IVec3::ZERO], &[IVec3::new(4, 4, 4)]);
+
+        let hit = world_manager.raycast(
+            Vec3::new(4.5, 4.25, 4.5),
+            Vec3::new(0.2, -1.0, 0.3),
+            8.0,
+            UnloadedChunks::Block,
+        );
+        assert_hit(hit, IVec3::new(4, 4, 4), FaceDirection::PosY, 0.0);
+    }
+
+    #[test]
+    fn axis_aligned_rays_hit_the_facing_side() {
+        let world_manager = world(&[IVec3::ZERO], &[IVec3::new(4, 4, 4)]);
+        let center = Vec3::splat(4.5);
+
+        for (direction, face) in [
+            (Vec3::X, FaceDirection::PosX),
+            (Vec3::NEG_X, FaceDirection::NegX),
+            (Vec3::Y, FaceDirection::PosY),
+            (Vec3::NEG_Y, FaceDirection::NegY),
+            (Vec3::Z, FaceDirection::PosZ),
+            (Vec3::NEG_Z, FaceDirection::NegZ),
+        ] {
+            // Start 3.5 voxels away on the side of `face` and aim back at it
+            let origin = center + direction * 3.5;
+            let hit = world_manager.raycast(origin, -direction, 8.0, UnloadedChunks::Block);
+            assert_hit(hit, IVec3::new(4, 4, 4), face, 3.0);
+
+            let miss = world_manager.raycast(origin, -direction, 2.5, UnloadedChunks::Block);
+            assert!(miss.is_none());
+        }
+    }
+
+    #[test]
+    fn rays_traverse_negative_coordinates() {
+        let chunk_pos = IVec3::splat(-1);
+        let world_manager = world(&[chunk_pos], &[IVec3::new(-6, -2, -3)]);
+
+        let hit = world_manager.raycast(
+            Vec3::new(-1.5, -1.5, -2.5),
+            Vec3::new(-1.0, -0.1, 0.0),
+            8.0,
+            UnloadedChunks::Block,
+        );
+        assert_hit(
+            hit,
+            IVec3::new(-6, -2, -3),
+            FaceDirection::PosX,
+            3.5 * 0.1f32.hypot(1.0),
+        );
+    }
+
+    #[test]
+    fn rays_cross_chunk_borders() {
+        let chunks = [IVec3::ZERO, IVec3::X, IVec3::new(1, 1, 0)];
+        let world_manager = world(&chunks, &[IVec3::new(34, 0, 0), IVec3::new(33, 33, 0)]);
+
+        let hit = world_manager.raycast(
+            Vec3::new(30.5, 0.5, 0.5),
+            Vec3::X,
+            8.0,
+            UnloadedChunks::Block,
+        );
+        assert_hit(hit, IVec3::new(34, 0, 0), FaceDirection::NegX, 3.5);
+
+        // Diagonally across the border to the chunk in front and then above
+        let hit = world_manager.raycast(
+            Vec3::new(30.5, 30.25, 0.5),
+            Vec3::new(1.0, 1.0, 0.0),
+            8.0,
+            UnloadedChunks::Block,
+        );
+        assert_hit(
+            hit,
+            IVec3::new(33, 33, 0),
+            FaceDirection::NegY,
+            2.75 * 2f32.sqrt(),
+        );
+    }
+
+    #[test]
+    fn unloaded_chunks_block_or_let_rays_through() {
+        let chunks = [IVec3::ZERO, IVec3::new(2, 0, 0)];
+        let world_manager = world(&chunks, &[IVec3::new(66, 0, 0)]);
+        let origin = Vec3::new(30.5, 0.5, 0.5);
+
+        let blocked = world_manager.raycast(origin, Vec3::X, 64.0, UnloadedChunks::Block);
+        assert!(blocked.is_none());
+
+        let hit = world_manager.raycast(origin, Vec3::X, 64.0, UnloadedChunks::PassThrough);
+        assert_hit(hit, IVec3::new(66, 0, 0), FaceDirection::NegX, 35.5);
+    }
+}
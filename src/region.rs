@@ -0,0 +1,541 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use bevy::{platform::collections::HashMap, prelude::*, tasks::IoTaskPool};
+use flate2::{Compression, read::ZlibDecoder, write::ZlibEncoder};
+
+use crate::{chunk::ChunkData, voxel_storage::VoxelStorage};
+
+/// Number of chunks along each axis of a region file.
+pub const REGION_SIZE: i32 = 16;
+const REGION_VOLUME: usize = (REGION_SIZE * REGION_SIZE * REGION_SIZE) as usize;
+
+const REGION_MAGIC: [u8; 4] = *b"VXRG";
+const REGION_VERSION: u32 = 1;
+
+/// Magic and version, followed by an `(offset, length)` pair of `u32`s per chunk.
+const HEADER_SIZE: u64 = 8 + REGION_VOLUME as u64 * 8;
+
+/// A file holding the saved chunks of a `REGION_SIZE`³ block of chunks.
+///
+/// The header maps every chunk to the offset and length of its zlib compressed
+/// payload, where a length of zero means the chunk was never saved. Payloads
+/// are never overwritten in place: a new one goes to the first gap between
+/// the payloads the header points to, or to the end of the file, and the
+/// space of the one it replaces is free once the header moves on.
+struct RegionFile {
+    file: File,
+    table: Vec<(u32, u32)>,
+}
+
+impl RegionFile {
+    /// Opens the region file at `path`, creating it when `create` is set.
+    ///
+    /// Returns `None` if the file doesn't exist and wasn't created.
+    fn open(path: &Path, create: bool) -> io::Result<Option<Self>> {
+        let file = match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(create)
+            .truncate(false)
+            .open(path)
+        {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound && !create => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        let mut region = Self {
+            file,
+            table: vec![(0, 0); REGION_VOLUME],
+        };
+
+        // Payloads only ever follow a complete header, so a shorter file was
+        // cut off while it was being created and has nothing saved in it yet
+        let file_length = region.file.metadata()?.len();
+        if file_length < HEADER_SIZE {
+            region.write_header()?;
+            region.file.sync_data()?;
+        } else {
+            region.read_header(file_length)?;
+        }
+
+        Ok(Some(region))
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+        header.extend_from_slice(&REGION_MAGIC);
+        header.extend_from_slice(&REGION_VERSION.to_le_bytes());
+        for (offset, length) in &self.table {
+            header.extend_from_slice(&offset.to_le_bytes());
+            header.extend_from_slice(&length.to_le_bytes());
+        }
+
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&header)
+    }
+
+    /// Reads the table of a file `file_length` bytes long, dropping entries
+    /// that point outside of it.
+    fn read_header(&mut self, file_length: u64) -> io::Result<()> {
+        let mut header = vec![0; HEADER_SIZE as usize];
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.read_exact(&mut header)?;
+
+        if header[0..4] != REGION_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a region file",
+            ));
+        }
+
+        let version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        if version != REGION_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported region version {version}"),
+            ));
+        }
+
+        for (index, (entry, bytes)) in self
+            .table
+            .iter_mut()
+            .zip(header[8..].chunks_exact(8))
+            .enumerate()
+        {
+            let offset = u32::from_le_bytes(bytes[0..4].try_into().unwrap());
+            let length = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+
+            if length > 0
+                && (u64::from(offset) < HEADER_SIZE
+                    || u64::from(offset) + u64::from(length) > file_length)
+            {
+                warn!("Dropping chunk {index} of region file, its payload lies outside the file");
+                continue;
+            }
+
+            *entry = (offset, length);
+        }
+
+        Ok(())
+    }
+
+    fn read_chunk(&mut self, index: usize) -> io::Result<Option<Vec<u8>>> {
+        let (offset, length) = self.table[index];
+        if length == 0 {
+            return Ok(None);
+        }
+
+        let mut payload = vec![0; length as usize];
+        self.file.seek(SeekFrom::Start(offset as u64))?;
+        self.file.read_exact(&mut payload)?;
+
+        Ok(Some(payload))
+    }
+
+    fn write_chunk(&mut self, index: usize, payload: &[u8]) -> io::Result<()> {
+        let too_large = |_| io::Error::new(io::ErrorKind::InvalidInput, "region file too large");
+        let offset = self.allocate(payload.len() as u64);
+        let entry = (
+            u32::try_from(offset).map_err(too_large)?,
+            u32::try_from(payload.len()).map_err(too_large)?,
+        );
+
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(payload)?;
+
+        // Only point the header at the payload once it is on disk, so a crash
+        // at any point leaves the previous payload in place
+        self.file.sync_data()?;
+
+        self.table[index] = entry;
+
+        let mut bytes = [0; 8];
+        bytes[0..4].copy_from_slice(&entry.0.to_le_bytes());
+        bytes[4..8].copy_from_slice(&entry.1.to_le_bytes());
+        self.file.seek(SeekFrom::Start(8 + index as u64 * 8))?;
+        self.file.write_all(&bytes)?;
+        self.file.sync_data()
+    }
+
+    /// Offset of the first `length` bytes after the header that no saved
+    /// payload uses.
+    fn allocate(&self, length: u64) -> u64 {
+        let mut used: Vec<(u64, u64)> = self
+            .table
+            .iter()
+            .filter(|(_, length)| *length > 0)
+            .map(|(offset, length)| (*offset as u64, *offset as u64 + *length as u64))
+            .collect();
+        used.sort_unstable();
+
+        let mut offset = HEADER_SIZE;
+        for (start, end) in used {
+            if start >= offset + length {
+                break;
+            }
+            offset = offset.max(end);
+        }
+
+        offset
+    }
+}
+
+/// On-disk storage for modified chunks, split into region files.
+#[derive(Resource, Clone)]
+pub struct ChunkStorage(Arc<ChunkStorageInner>);
+
+struct ChunkStorageInner {
+    directory: PathBuf,
+    regions: Mutex<HashMap<IVec3, Arc<Mutex<RegionFile>>>>,
+    /// Chunks queued for saving, which loads are served from until written.
+    pending: Mutex<HashMap<IVec3, PendingSave>>,
+    next_generation: AtomicU64,
+}
+
+/// Voxels of a chunk queued for saving.
+///
+/// Every save gets a new generation. Writes check it under the lock of the
+/// region file, so only the newest save of a chunk is ever written, however
+/// the tasks writing them are scheduled.
+struct PendingSave {
+    generation: u64,
+    voxels: Arc<VoxelStorage>,
+}
+
+impl ChunkStorage {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self(Arc::new(ChunkStorageInner {
+            directory: directory.into(),
+            regions: Mutex::new(HashMap::new()),
+            pending: Mutex::new(HashMap::new()),
+            next_generation: AtomicU64::new(0),
+        }))
+    }
+
+    /// Returns the saved voxels of the chunk at `position`, if it was saved.
+    pub fn load_chunk(&self, position: IVec3) -> io::Result<Option<VoxelStorage>> {
+        if let Some(pending) = self.0.pending.lock().unwrap().get(&position) {
+            return Ok(Some(VoxelStorage::clone(&pending.voxels)));
+        }
+
+        let (region_pos, index) = Self::region_index(position);
+        let Some(region) = self.region(region_pos, false)? else {
+            return Ok(None);
+        };
+
+        let Some(payload) = region.lock().unwrap().read_chunk(index)? else {
+            return Ok(None);
+        };
+
+        let mut decoder = ZlibDecoder::new(payload.as_slice());
+        let volume = ChunkData::SIZE * ChunkData::SIZE * ChunkData::SIZE;
+
+        VoxelStorage::read_from(&mut decoder, volume).map(Some)
+    }
+
+    /// Writes the voxels of the chunk at `position`, blocking until done.
+    pub fn save_chunk(&self, position: IVec3, voxels: &VoxelStorage) -> io::Result<()> {
+        let payload = Self::encode(voxels)?;
+        self.region_file(position)?
+            .lock()
+            .unwrap()
+            .write_chunk(Self::region_index(position).1, &payload)
+    }
+
+    /// Saves `chunk` on the IO task pool.
+    pub fn save_chunk_async(&self, chunk: &ChunkData) {
+        let position = chunk.position;
+        let generation = self.queue_save(chunk);
+
+        let storage = self.clone();
+        IoTaskPool::get()
+            .spawn(async move {
+                if let Err(err) = storage.write_pending(position, generation) {
+                    error!("Failed to save chunk {position}: {err}");
+                }
+            })
+            .detach();
+    }
+
+    /// Queues the voxels of `chunk` for saving, returning the generation of
+    /// the save.
+    fn queue_save(&self, chunk: &ChunkData) -> u64 {
+        let mut voxels = chunk.voxels().clone();
+        voxels.compact();
+
+        let generation = self.0.next_generation.fetch_add(1, Ordering::Relaxed);
+        self.0.pending.lock().unwrap().insert(
+            chunk.position,
+            PendingSave {
+                generation,
+                voxels: Arc::new(voxels),
+            },
+        );
+
+        generation
+    }
+
+    /// Writes the save of the chunk at `position` queued as `generation`,
+    /// unless a newer one was queued since or it was written already.
+    fn write_pending(&self, position: IVec3, generation: u64) -> io::Result<()> {
+        let is_queued = |pending: &HashMap<IVec3, PendingSave>| {
+            pending
+                .get(&position)
+                .is_some_and(|queued| queued.generation == generation)
+        };
+
+        let voxels = match self.0.pending.lock().unwrap().get(&position) {
+            Some(queued) if queued.generation == generation => queued.voxels.clone(),
+            _ => return Ok(()),
+        };
+        let payload = Self::encode(&voxels)?;
+
+        let region = self.region_file(position)?;
+        let mut region = region.lock().unwrap();
+
+        // Checked again now that no other write to the region can happen
+        if !is_queued(&self.0.pending.lock().unwrap()) {
+            return Ok(());
+        }
+
+        let result = region.write_chunk(Self::region_index(position).1, &payload);
+
+        let mut pending = self.0.pending.lock().unwrap();
+        if is_queued(&pending) {
+            pending.remove(&position);
+        }
+
+        result
+    }
+
+    /// Writes every chunk still queued for saving, blocking until done.
+    pub fn flush_pending(&self) {
+        let pending: Vec<_> = self
+            .0
+            .pending
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(position, queued)| (*position, queued.generation))
+            .collect();
+
+        for (position, generation) in pending {
+            if let Err(err) = self.write_pending(position, generation) {
+                error!("Failed to save chunk {position}: {err}");
+            }
+        }
+    }
+
+    fn encode(voxels: &VoxelStorage) -> io::Result<Vec<u8>> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+        voxels.write_to(&mut encoder)?;
+        encoder.finish()
+    }
+
+    /// Region file of the chunk at `position`, created if needed.
+    fn region_file(&self, position: IVec3) -> io::Result<Arc<Mutex<RegionFile>>> {
+        let (region_pos, _) = Self::region_index(position);
+        Ok(self
+            .region(region_pos, true)?
+            .expect("region files are created on demand"))
+    }
+
+    /// Region containing the chunk at `position` and the chunk's index in it.
+    fn region_index(position: IVec3) -> (IVec3, usize) {
+        let region_pos = position.div_euclid(IVec3::splat(REGION_SIZE));
+        let local = position.rem_euclid(IVec3::splat(REGION_SIZE));
+        let index = local.x + local.y * REGION_SIZE + local.z * REGION_SIZE * REGION_SIZE;
+
+        (region_pos, index as usize)
+    }
+
+    fn region(
+        &self,
+        region_pos: IVec3,
+        create: bool,
+    ) -> io::Result<Option<Arc<Mutex<RegionFile>>>> {
+        let mut regions = self.0.regions.lock().unwrap();
+        if let Some(region) = regions.get(&region_pos) {
+            return Ok(Some(region.clone()));
+        }
+
+        if create {
+            fs::create_dir_all(&self.0.directory)?;
+        }
+
+        let path = self.0.directory.join(format!(
+            "r.{}.{}.{}.region",
+            region_pos.x, region_pos.y, region_pos.z
+        ));
+
+        let Some(region) = RegionFile::open(&path, create)? else {
+            return Ok(None);
+        };
+
+        let region = Arc::new(Mutex::new(region));
+        regions.insert(region_pos, region.clone());
+
+        Ok(Some(region))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::voxel::Voxel;
+
+    /// Empty directory under the system temporary directory, removed again
+    /// when dropped.
+    pub(crate) struct TemporaryDirectory(pub(crate) PathBuf);
+
+    impl TemporaryDirectory {
+        pub(crate) fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("voxel-engine-{}-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TemporaryDirectory {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn region_file_round_trips_payloads() {
+        let directory = TemporaryDirectory::new("region-file");
+        let path = directory.0.join("r.0.0.0.region");
+
+        let mut region = RegionFile::open(&path, true).unwrap().unwrap();
+        region.write_chunk(0, &[1; 100]).unwrap();
+        region.write_chunk(7, &[2; 50]).unwrap();
+
+        // Overwritten with a larger and a smaller payload
+        region.write_chunk(0, &[3; 300]).unwrap();
+        region.write_chunk(7, &[4; 10]).unwrap();
+
+        drop(region);
+        let mut region = RegionFile::open(&path, false).unwrap().unwrap();
+
+        assert_eq!(region.read_chunk(0).unwrap(), Some(vec![3; 300]));
+        assert_eq!(region.read_chunk(7).unwrap(), Some(vec![4; 10]));
+        assert_eq!(region.read_chunk(1).unwrap(), None);
+
+        // The larger payload went after both old ones, and the smaller one
+        // into the space the first payload left behind
+        assert_eq!(region.table[0], (HEADER_SIZE as u32 + 150, 300));
+        assert_eq!(region.table[7], (HEADER_SIZE as u32, 10));
+        assert_eq!(fs::metadata(&path).unwrap().len(), HEADER_SIZE + 450);
+    }
+
+    #[test]
+    fn only_the_newest_save_of_a_chunk_is_written() {
+        let directory = TemporaryDirectory::new("save-order");
+        let chunk_storage = ChunkStorage::new(&directory.0);
+
+        let saved = |voxel: Voxel| {
+            let mut chunk_data = ChunkData::new(IVec3::new(3, -1, 2));
+            chunk_data.fill(voxel);
+            chunk_data
+        };
+
+        // The tasks writing both saves finish in the wrong order
+        let older = chunk_storage.queue_save(&saved(Voxel::Stone));
+        let newer = chunk_storage.queue_save(&saved(Voxel::Dirt));
+        chunk_storage
+            .write_pending(IVec3::new(3, -1, 2), newer)
+            .unwrap();
+        chunk_storage
+            .write_pending(IVec3::new(3, -1, 2), older)
+            .unwrap();
+
+        assert!(chunk_storage.0.pending.lock().unwrap().is_empty());
+        let loaded = chunk_storage
+            .load_chunk(IVec3::new(3, -1, 2))
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.uniform_voxel(), Some(Voxel::Dirt));
+
+        // Flushing at exit doesn't write older saves still queued either
+        let older = chunk_storage.queue_save(&saved(Voxel::Sand));
+        chunk_storage.queue_save(&saved(Voxel::Grass));
+        chunk_storage.flush_pending();
+        chunk_storage
+            .write_pending(IVec3::new(3, -1, 2), older)
+            .unwrap();
+
+        let loaded = chunk_storage
+            .load_chunk(IVec3::new(3, -1, 2))
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.uniform_voxel(), Some(Voxel::Grass));
+    }
+
+    #[test]
+    fn damaged_region_files_are_recovered() {
+        let directory = TemporaryDirectory::new("damaged-region");
+        let path = directory.0.join("r.0.0.0.region");
+
+        // Cut off while the header was first written
+        fs::write(&path, &REGION_MAGIC[..3]).unwrap();
+        let mut region = RegionFile::open(&path, false).unwrap().unwrap();
+        assert_eq!(region.read_chunk(0).unwrap(), None);
+        region.write_chunk(0, &[1; 100]).unwrap();
+        region.write_chunk(1, &[2; 100]).unwrap();
+        drop(region);
+
+        // Cut off in the middle of the second payload
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(HEADER_SIZE + 150).unwrap();
+        drop(file);
+
+        let mut region = RegionFile::open(&path, false).unwrap().unwrap();
+        assert_eq!(region.read_chunk(0).unwrap(), Some(vec![1; 100]));
+        assert_eq!(region.read_chunk(1).unwrap(), None);
+    }
+
+    #[test]
+    fn missing_region_files_are_only_created_when_asked() {
+        let directory = TemporaryDirectory::new("missing-region");
+        let path = directory.0.join("r.0.0.0.region");
+
+        assert!(RegionFile::open(&path, false).unwrap().is_none());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn chunk_storage_round_trips_voxels() {
+        let directory = TemporaryDirectory::new("chunk-storage");
+        let chunk_storage = ChunkStorage::new(&directory.0);
+
+        let volume = ChunkData::SIZE * ChunkData::SIZE * ChunkData::SIZE;
+        let mut voxels = VoxelStorage::new(volume, Voxel::Stone);
+        for index in (0..volume).step_by(7) {
+            voxels.set(index, Voxel::ALL[index % Voxel::ALL.len()]);
+        }
+
+        // Chunks on both sides of a region border
+        let positions = [IVec3::new(-1, 0, 3), IVec3::new(0, 0, 3)];
+        for position in positions {
+            chunk_storage.save_chunk(position, &voxels).unwrap();
+        }
+
+        for position in positions {
+            let loaded = chunk_storage.load_chunk(position).unwrap().unwrap();
+            assert!(loaded.iter().eq(voxels.iter()));
+        }
+        assert!(chunk_storage.load_chunk(IVec3::ONE).unwrap().is_none());
+    }
+}
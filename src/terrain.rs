@@ -1,84 +1,505 @@
-use bevy::math::IVec3;
-use noise::{HybridMulti, NoiseFn, Perlin};
+use bevy::math::{DVec3, IVec3};
+use noise::{Fbm, HybridMulti, NoiseFn, Perlin};
+use serde::{Deserialize, Serialize};
 
-use crate::voxel::Voxel;
+use crate::{chunk::ChunkData, voxel::Voxel};
 
+/// Deepest surface layer any biome places above plain stone.
+const MAX_SURFACE_DEPTH: f64 = 6.0;
+
+/// Distance in climate space over which neighboring biomes blend into each
+/// other, larger values give wider and smoother transitions.
+const BIOME_BLEND: f64 = 0.2;
+
+/// Height above which mountains are covered in snow, relative to
+/// `base_height` and in units of `height_amplitude`.
+const SNOW_LINE: f64 = 1.6;
+
+/// Squashes the output of the fractal noises, which reaches well past
+/// `-1.0..1.0` in its tails, into that range.
+fn normalize(noise: f64) -> f64 {
+    (noise * 0.6).tanh()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Biome {
     Plains,
     Desert,
+    Forest,
+    Tundra,
+    Mountains,
+    Ocean,
+    Swamp,
+}
+
+impl Biome {
+    pub const ALL: [Biome; 7] = [
+        Biome::Plains,
+        Biome::Desert,
+        Biome::Forest,
+        Biome::Tundra,
+        Biome::Mountains,
+        Biome::Ocean,
+        Biome::Swamp,
+    ];
+
+    /// Climate this biome is the most typical for.
+    fn climate(self) -> Climate {
+        let (temperature, humidity, continentalness) = match self {
+            Biome::Plains => (0.0, -0.1, 0.1),
+            Biome::Desert => (0.45, -0.35, 0.1),
+            Biome::Forest => (0.0, 0.35, 0.15),
+            Biome::Tundra => (-0.45, 0.0, 0.15),
+            Biome::Mountains => (-0.1, 0.0, 0.6),
+            Biome::Ocean => (0.0, 0.0, -0.6),
+            Biome::Swamp => (0.25, 0.45, -0.15),
+        };
+
+        Climate {
+            temperature,
+            humidity,
+            continentalness,
+        }
+    }
+
+    /// Height of the surface relative to `base_height`, and how far the height
+    /// noise moves it up or down, both in units of `height_amplitude`.
+    fn height(self) -> (f64, f64) {
+        match self {
+            Biome::Plains => (0.05, 0.35),
+            Biome::Desert => (0.05, 0.3),
+            Biome::Forest => (0.2, 0.6),
+            Biome::Tundra => (0.25, 0.5),
+            Biome::Mountains => (1.5, 2.0),
+            Biome::Ocean => (-1.2, 0.3),
+            Biome::Swamp => (-0.15, 0.1),
+        }
+    }
+
+    /// Voxels covering the stone, each down to the given depth below the
+    /// surface.
+    fn surface_layers(self) -> &'static [(Voxel, f64)] {
+        match self {
+            Biome::Plains | Biome::Forest | Biome::Swamp => {
+                &[(Voxel::Grass, 1.0), (Voxel::Dirt, 4.0)]
+            }
+            Biome::Desert => &[(Voxel::Sand, 1.0), (Voxel::SandStone, 6.0)],
+            Biome::Tundra => &[(Voxel::Snow, 1.0), (Voxel::Dirt, 3.0)],
+            Biome::Mountains => &[],
+            Biome::Ocean => &[(Voxel::Sand, 3.0)],
+        }
+    }
+}
+
+/// Climate of a column, each value in `-1.0..1.0`.
+#[derive(Clone, Copy, Debug)]
+pub struct Climate {
+    pub temperature: f64,
+    pub humidity: f64,
+    /// How far inland the column is, low values are ocean.
+    pub continentalness: f64,
+}
+
+impl Climate {
+    fn distance_squared(self, other: Climate) -> f64 {
+        (self.temperature - other.temperature).powi(2)
+            + (self.humidity - other.humidity).powi(2)
+            + (self.continentalness - other.continentalness).powi(2)
+    }
+}
+
+/// Terrain of a single x/z column, shared by every voxel in it.
+#[derive(Clone, Copy, Debug)]
+pub struct TerrainColumn {
+    /// Height of the surface. Voxels at or below it are solid, unless carved
+    /// out by a cave.
+    pub height: f64,
+    /// Biome whose climate is closest, which decides the surface voxels.
+    pub biome: Biome,
+}
+
+/// Parameters shaping the generated terrain, independent of the seed.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TerrainSettings {
+    /// Height biome heights are relative to.
+    pub base_height: f64,
+    /// Vertical scale of biome heights and of the height noise.
+    pub height_amplitude: f64,
+    /// Horizontal size of terrain features, in voxels.
+    pub height_scale: f64,
+    /// Horizontal size of temperature and humidity changes, in voxels.
+    pub biome_scale: f64,
+    /// Horizontal size of continents and oceans, in voxels.
+    pub continent_scale: f64,
+    /// Size of cheese caves, the large open caverns, in voxels.
+    pub cheese_scale: f64,
+    /// Cheese noise value above which voxels are carved out, higher values
+    /// give fewer and smaller caverns.
+    pub cheese_threshold: f64,
+    /// Length of the winding spaghetti tunnels, in voxels.
+    pub spaghetti_scale: f64,
+    /// How far both spaghetti noises may be from zero for a voxel to be
+    /// carved out, higher values give wider tunnels.
+    pub spaghetti_width: f64,
+    /// Height below which caves are no longer carved.
+    pub cave_floor: f64,
+    /// Height up to which the air above the surface is filled with water.
+    pub sea_level: f64,
+}
+
+impl Default for TerrainSettings {
+    fn default() -> Self {
+        Self {
+            base_height: 5.,
+            height_amplitude: 20.,
+            height_scale: 100.,
+            biome_scale: 500.,
+            continent_scale: 1500.,
+            cheese_scale: 80.,
+            cheese_threshold: 0.3,
+            spaghetti_scale: 60.,
+            spaghetti_width: 0.06,
+            cave_floor: -64.,
+            sea_level: 2.,
+        }
+    }
 }
 
 pub struct TerrainGenerator {
+    seed: u32,
     height_noise: HybridMulti<Perlin>,
     temperature_noise: HybridMulti<Perlin>,
-    base_height: f64,
+    humidity_noise: HybridMulti<Perlin>,
+    continentalness_noise: HybridMulti<Perlin>,
+    cheese_noise: Fbm<Perlin>,
+    /// Spaghetti tunnels follow where both noises are close to zero.
+    spaghetti_noise: [Perlin; 2],
+    settings: TerrainSettings,
 }
 
 impl TerrainGenerator {
-    pub fn new(seed: u32) -> Self {
+    /// Bumped whenever the same seed and settings start producing different
+    /// terrain, so worlds saved with an older generator can be detected.
+    pub const VERSION: u32 = 6;
+
+    pub fn new(seed: u32, settings: TerrainSettings) -> Self {
         let mut height_noise = HybridMulti::<Perlin>::new(seed);
         height_noise.octaves = 5;
         height_noise.frequency = 1.1;
         height_noise.lacunarity = 2.8;
         height_noise.persistence = 0.4;
 
-        let mut temperature_noise = HybridMulti::<Perlin>::new(seed + 1000);
+        let mut temperature_noise = HybridMulti::<Perlin>::new(seed.wrapping_add(1000));
         temperature_noise.octaves = 3;
         temperature_noise.frequency = 0.8;
         temperature_noise.lacunarity = 2.0;
         temperature_noise.persistence = 0.5;
 
+        let mut humidity_noise = HybridMulti::<Perlin>::new(seed.wrapping_add(1001));
+        humidity_noise.octaves = 3;
+        humidity_noise.frequency = 0.8;
+        humidity_noise.lacunarity = 2.0;
+        humidity_noise.persistence = 0.5;
+
+        let mut continentalness_noise = HybridMulti::<Perlin>::new(seed.wrapping_add(1002));
+        continentalness_noise.octaves = 4;
+        continentalness_noise.frequency = 1.0;
+        continentalness_noise.lacunarity = 2.0;
+        continentalness_noise.persistence = 0.5;
+
+        let mut cheese_noise = Fbm::<Perlin>::new(seed.wrapping_add(2000));
+        cheese_noise.octaves = 2;
+
+        let spaghetti_noise = [
+            Perlin::new(seed.wrapping_add(3000)),
+            Perlin::new(seed.wrapping_add(3001)),
+        ];
+
         Self {
+            seed,
             height_noise,
             temperature_noise,
-            base_height: 5.,
+            humidity_noise,
+            continentalness_noise,
+            cheese_noise,
+            spaghetti_noise,
+            settings,
+        }
+    }
+
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    /// Returns the voxel filling the whole chunk at `chunk_pos` when it lies
+    /// entirely above or below the range the surface can reach.
+    pub fn uniform_chunk(&self, chunk_pos: IVec3) -> Option<Voxel> {
+        let min_y = (chunk_pos.y * ChunkData::SIZE as i32) as f64;
+        let max_y = min_y + (ChunkData::SIZE - 1) as f64;
+
+        let (lowest_surface, highest_surface) = self.surface_range();
+
+        if min_y > highest_surface {
+            if min_y > self.settings.sea_level {
+                Some(Voxel::Air)
+            } else if max_y <= self.settings.sea_level {
+                Some(Voxel::Water)
+            } else {
+                None
+            }
+        } else if max_y <= lowest_surface - MAX_SURFACE_DEPTH && max_y < self.settings.cave_floor {
+            Some(Voxel::Stone)
+        } else {
+            None
+        }
+    }
+
+    /// Lowest and highest height the surface can reach anywhere.
+    pub fn surface_range(&self) -> (f64, f64) {
+        // The raw height noise isn't bounded, but `normalize` squashes it into
+        // -1..1, and blending biomes never leaves the range of the biomes
+        // involved
+        let (lowest, highest) = Biome::ALL.iter().fold(
+            (f64::INFINITY, f64::NEG_INFINITY),
+            |(lowest, highest), biome| {
+                let (offset, roughness) = biome.height();
+                (
+                    lowest.min(offset - roughness),
+                    highest.max(offset + roughness),
+                )
+            },
+        );
+
+        let TerrainSettings {
+            base_height,
+            height_amplitude,
+            ..
+        } = self.settings;
+
+        (
+            base_height + lowest * height_amplitude,
+            base_height + highest * height_amplitude,
+        )
+    }
+
+    /// Height of the highest terrain voxel in the column at `x`, `z`, before
+    /// caves are carved out of it.
+    pub fn surface_height(&self, x: i32, z: i32) -> i32 {
+        self.column(x, z).height.floor() as i32
+    }
+
+    pub fn climate(&self, x: i32, z: i32) -> Climate {
+        let biome = [
+            x as f64 / self.settings.biome_scale,
+            z as f64 / self.settings.biome_scale,
+        ];
+        let continent = [
+            x as f64 / self.settings.continent_scale,
+            z as f64 / self.settings.continent_scale,
+        ];
+
+        Climate {
+            temperature: normalize(self.temperature_noise.get(biome)),
+            humidity: normalize(self.humidity_noise.get(biome)),
+            continentalness: normalize(self.continentalness_noise.get(continent)),
+        }
+    }
+
+    /// Computes the biome and surface height of the column at `x`, `z`.
+    ///
+    /// Every biome is weighted by how close its climate is to the column's,
+    /// falling off smoothly over [`BIOME_BLEND`], and the height parameters are
+    /// the weighted average of all biomes. This way the surface rises and
+    /// flattens gradually across biome borders instead of changing abruptly
+    /// where the closest biome switches.
+    pub fn column(&self, x: i32, z: i32) -> TerrainColumn {
+        let climate = self.climate(x, z);
+
+        let mut biome = Biome::Plains;
+        let mut best_weight = 0.0;
+        let mut total_weight = 0.0;
+        let mut offset = 0.0;
+        let mut roughness = 0.0;
+
+        for candidate in Biome::ALL {
+            let distance_squared = climate.distance_squared(candidate.climate());
+            let weight = (-distance_squared / (BIOME_BLEND * BIOME_BLEND)).exp();
+
+            let (biome_offset, biome_roughness) = candidate.height();
+            offset += biome_offset * weight;
+            roughness += biome_roughness * weight;
+            total_weight += weight;
+
+            if weight > best_weight {
+                best_weight = weight;
+                biome = candidate;
+            }
+        }
+
+        let total_weight = total_weight.max(f64::MIN_POSITIVE);
+        let offset = offset / total_weight;
+        let roughness = roughness / total_weight;
+
+        let TerrainSettings {
+            base_height,
+            height_amplitude,
+            height_scale,
+            ..
+        } = self.settings;
+
+        let noise = normalize(
+            self.height_noise
+                .get([x as f64 / height_scale, z as f64 / height_scale]),
+        );
+
+        TerrainColumn {
+            height: base_height + (offset + noise * roughness) * height_amplitude,
+            biome,
         }
     }
 
-    fn get_biome(&self, world_pos: IVec3) -> Biome {
-        let temperature = self
-            .temperature_noise
-            .get([world_pos.x as f64 / 500.0, world_pos.z as f64 / 500.0]);
+    /// Whether the voxel at `world_pos` is carved out by a cave.
+    ///
+    /// Cheese caves are the blobs where a 3D noise exceeds a threshold, while
+    /// spaghetti caves are the thin tunnels along which two independent 3D
+    /// noises are both close to zero.
+    fn is_cave(&self, world_pos: IVec3) -> bool {
+        let TerrainSettings {
+            cheese_scale,
+            cheese_threshold,
+            spaghetti_scale,
+            spaghetti_width,
+            cave_floor,
+            ..
+        } = self.settings;
 
-        match temperature {
-            t if t > 0.2 => Biome::Desert,
-            _ => Biome::Plains,
+        if (world_pos.y as f64) < cave_floor {
+            return false;
         }
+
+        let position = world_pos.as_dvec3();
+
+        // Caverns are squashed vertically so they spread out more than up
+        let cheese = position / DVec3::new(cheese_scale, cheese_scale * 0.5, cheese_scale);
+        if self.cheese_noise.get(cheese.to_array()) > cheese_threshold {
+            return true;
+        }
+
+        let spaghetti = (position / spaghetti_scale).to_array();
+        self.spaghetti_noise
+            .iter()
+            .all(|noise| noise.get(spaghetti).abs() < spaghetti_width)
+    }
+
+    pub fn get_biome(&self, world_pos: IVec3) -> Biome {
+        self.column(world_pos.x, world_pos.z).biome
     }
 
     pub fn get_voxel(&self, world_pos: IVec3) -> Voxel {
-        let IVec3 { x, y, z } = world_pos;
-
-        let biome = self.get_biome(world_pos);
-
-        let height =
-            self.base_height + self.height_noise.get([x as f64 / 100.0, z as f64 / 100.0]) * 20.0;
-
-        let depth_below_surface = height - (y as f64);
-
-        if (y as f64) <= height {
-            match biome {
-                Biome::Plains => {
-                    if depth_below_surface < 1.0 {
-                        Voxel::Grass
-                    } else if depth_below_surface < 4.0 {
-                        Voxel::Dirt
-                    } else {
-                        Voxel::Stone
-                    }
-                }
-                Biome::Desert => {
-                    if depth_below_surface < 1.0 {
-                        Voxel::Sand
-                    } else if depth_below_surface < 6.0 {
-                        Voxel::SandStone
-                    } else {
-                        Voxel::Stone
-                    }
-                }
+        self.column_voxel(&self.column(world_pos.x, world_pos.z), world_pos)
+    }
+
+    /// Voxel at `world_pos`, which must lie in `column`.
+    ///
+    /// Lets callers filling whole columns compute the column only once.
+    pub fn column_voxel(&self, column: &TerrainColumn, world_pos: IVec3) -> Voxel {
+        let y = world_pos.y as f64;
+        if y > column.height {
+            return if y <= self.settings.sea_level {
+                Voxel::Water
+            } else {
+                Voxel::Air
+            };
+        }
+
+        // Caves stay dry, only open water above the surface is filled
+        if self.is_cave(world_pos) {
+            return Voxel::Air;
+        }
+
+        let depth_below_surface = column.height - y;
+
+        let snow_line = self.settings.base_height + SNOW_LINE * self.settings.height_amplitude;
+        if column.biome == Biome::Mountains
+            && column.height > snow_line
+            && depth_below_surface < 1.0
+        {
+            return Voxel::Snow;
+        }
+
+        let voxel = column
+            .biome
+            .surface_layers()
+            .iter()
+            .find(|(_, depth)| depth_below_surface < *depth)
+            .map_or(Voxel::Stone, |(voxel, _)| *voxel);
+
+        // Nothing grows at the bottom of lakes
+        match voxel {
+            Voxel::Grass | Voxel::Snow if column.height <= self.settings.sea_level => Voxel::Dirt,
+            voxel => voxel,
+        }
+    }
+}
+
+/// A generator whose surface lies far below the chunks tests build by hand,
+/// so they start out under the open sky.
+#[cfg(test)]
+impl TerrainGenerator {
+    pub(crate) fn sunken() -> Self {
+        Self::new(
+            0,
+            TerrainSettings {
+                base_height: -1000.0,
+                height_amplitude: 0.0,
+                ..TerrainSettings::default()
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::SplitMix64;
+
+    #[test]
+    fn surface_stays_within_surface_range() {
+        let terrain_generator = TerrainGenerator::new(12345, TerrainSettings::default());
+        let (lowest, highest) = terrain_generator.surface_range();
+
+        let mut random = SplitMix64::new(1);
+        for _ in 0..10_000 {
+            let x = random.range(-100_000, 100_000);
+            let z = random.range(-100_000, 100_000);
+
+            let height = terrain_generator.column(x, z).height;
+            assert!((lowest..=highest).contains(&height), "{height} at {x}, {z}");
+        }
+    }
+
+    #[test]
+    fn uniform_chunks_match_generated_voxels() {
+        let terrain_generator = TerrainGenerator::new(12345, TerrainSettings::default());
+        let size = ChunkData::SIZE as i32;
+
+        let mut random = SplitMix64::new(2);
+        for y in -12..=12 {
+            let chunk_pos = IVec3::new(random.range(-50, 50), y, random.range(-50, 50));
+            let Some(voxel) = terrain_generator.uniform_chunk(chunk_pos) else {
+                continue;
+            };
+
+            for _ in 0..64 {
+                let local = IVec3::new(
+                    random.range(0, size),
+                    random.range(0, size),
+                    random.range(0, size),
+                );
+                let world_pos = chunk_pos * size + local;
+                assert_eq!(terrain_generator.get_voxel(world_pos), voxel, "{world_pos}");
             }
-        } else {
-            Voxel::Air
         }
     }
 }
@@ -9,7 +9,10 @@ pub struct MeshStats {
     pub algorithm: String,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+/// Discriminants are the ids voxels are saved with, so existing variants must
+/// keep their position and new ones are added at the end.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[repr(u8)]
 pub enum Voxel {
     Air,
     Dirt,
@@ -17,34 +20,190 @@ pub enum Voxel {
     Stone,
     Sand,
     SandStone,
+    Glowstone,
+    Lamp,
+    CoalOre,
+    IronOre,
+    GoldOre,
+    DiamondOre,
+    Wood,
+    Leaves,
+    Cactus,
+    Snow,
+    Water,
+}
+
+/// How the faces of a voxel are drawn, each class getting its own mesh and
+/// material per chunk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RenderClass {
+    /// Hides everything behind it, drawn without blending.
+    Opaque,
+    /// Either fully opaque or fully transparent per pixel, drawn alpha tested
+    /// so it still writes depth and needs no sorting.
+    Cutout,
+    /// Partially see-through, drawn alpha blended after everything else.
+    Translucent,
+}
+
+impl RenderClass {
+    pub const ALL: [RenderClass; 3] = [
+        RenderClass::Opaque,
+        RenderClass::Cutout,
+        RenderClass::Translucent,
+    ];
 }
 
 pub struct VoxelMaterial {
     pub color: Color,
+    /// Block light level emitted, from 0 for none up to `LightLevel::MAX`.
+    /// Emissive voxels are also drawn at full brightness.
+    pub emission: u8,
 }
 
 impl Voxel {
+    /// Every voxel type, indexed by id.
+    pub const ALL: [Voxel; 17] = [
+        Voxel::Air,
+        Voxel::Dirt,
+        Voxel::Grass,
+        Voxel::Stone,
+        Voxel::Sand,
+        Voxel::SandStone,
+        Voxel::Glowstone,
+        Voxel::Lamp,
+        Voxel::CoalOre,
+        Voxel::IronOre,
+        Voxel::GoldOre,
+        Voxel::DiamondOre,
+        Voxel::Wood,
+        Voxel::Leaves,
+        Voxel::Cactus,
+        Voxel::Snow,
+        Voxel::Water,
+    ];
+
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_id(id: u8) -> Option<Voxel> {
+        Self::ALL.get(id as usize).copied()
+    }
+
     pub fn is_solid(&self) -> bool {
-        !matches!(self, Voxel::Air)
+        !matches!(self, Voxel::Air | Voxel::Water)
+    }
+
+    pub fn render_class(&self) -> RenderClass {
+        // Leaves belong into the cutout class, but voxels are only colored
+        // by their vertices, which leaves them no alpha pattern to cut out.
+        // Until they get one they are drawn opaque.
+        match self {
+            Voxel::Water => RenderClass::Translucent,
+            _ => RenderClass::Opaque,
+        }
+    }
+
+    /// Whether nothing behind this voxel can be seen through it.
+    pub fn is_opaque(&self) -> bool {
+        *self != Voxel::Air && self.render_class() == RenderClass::Opaque
+    }
+
+    /// Whether the face of this voxel touching `neighbor` is drawn.
+    ///
+    /// Faces are hidden behind opaque voxels and between voxels of the same
+    /// type, so opaque voxels show through cutout voxels and water. Water is
+    /// hidden behind cutout voxels as well, as its surface would otherwise
+    /// show through their gaps where they hang into it, so it only shows
+    /// against air.
+    pub fn is_face_visible(self, neighbor: Voxel) -> bool {
+        self != Voxel::Air
+            && !neighbor.is_opaque()
+            && neighbor != self
+            && !(self.render_class() == RenderClass::Translucent
+                && neighbor.render_class() == RenderClass::Cutout)
+    }
+
+    /// Whether light can't pass through this voxel.
+    pub fn blocks_light(&self) -> bool {
+        self.is_solid()
+    }
+
+    /// Block light level this voxel emits, 0 for none.
+    pub fn light_emission(&self) -> u8 {
+        self.get_material().emission
     }
 
     pub fn get_material(&self) -> VoxelMaterial {
         match self {
-            Voxel::Air => VoxelMaterial { color: Color::NONE },
+            Voxel::Air => VoxelMaterial {
+                color: Color::NONE,
+                emission: 0,
+            },
             Voxel::Dirt => VoxelMaterial {
                 color: Color::srgb(0.55, 0.27, 0.07),
+                emission: 0,
             },
             Voxel::Grass => VoxelMaterial {
                 color: Color::srgb(0.34, 0.69, 0.31),
+                emission: 0,
             },
             Voxel::Stone => VoxelMaterial {
                 color: Color::srgb(0.60, 0.60, 0.60),
+                emission: 0,
             },
             Voxel::Sand => VoxelMaterial {
                 color: Color::srgb(0.93, 0.86, 0.51),
+                emission: 0,
             },
             Voxel::SandStone => VoxelMaterial {
                 color: Color::srgb(0.76, 0.70, 0.50),
+                emission: 0,
+            },
+            Voxel::Glowstone => VoxelMaterial {
+                color: Color::srgb(0.98, 0.83, 0.45),
+                emission: 15,
+            },
+            Voxel::Lamp => VoxelMaterial {
+                color: Color::srgb(1.0, 0.95, 0.80),
+                emission: 12,
+            },
+            Voxel::CoalOre => VoxelMaterial {
+                color: Color::srgb(0.20, 0.20, 0.22),
+                emission: 0,
+            },
+            Voxel::IronOre => VoxelMaterial {
+                color: Color::srgb(0.72, 0.55, 0.45),
+                emission: 0,
+            },
+            Voxel::GoldOre => VoxelMaterial {
+                color: Color::srgb(0.95, 0.78, 0.20),
+                emission: 0,
+            },
+            Voxel::DiamondOre => VoxelMaterial {
+                color: Color::srgb(0.40, 0.90, 0.90),
+                emission: 0,
+            },
+            Voxel::Wood => VoxelMaterial {
+                color: Color::srgb(0.45, 0.32, 0.18),
+                emission: 0,
+            },
+            Voxel::Leaves => VoxelMaterial {
+                color: Color::srgb(0.18, 0.50, 0.16),
+                emission: 0,
+            },
+            Voxel::Cactus => VoxelMaterial {
+                color: Color::srgb(0.30, 0.60, 0.25),
+                emission: 0,
+            },
+            Voxel::Snow => VoxelMaterial {
+                color: Color::srgb(0.95, 0.97, 1.0),
+                emission: 0,
+            },
+            Voxel::Water => VoxelMaterial {
+                color: Color::srgba(0.20, 0.45, 0.85, 0.6),
+                emission: 0,
             },
         }
     }
@@ -0,0 +1,367 @@
+use std::io::{self, Read, Write};
+
+use crate::voxel::Voxel;
+
+/// Palette compressed voxel array.
+///
+/// Every distinct voxel type stored is kept once in `palette`, and each voxel is
+/// a bit-packed index into it. The index width starts at zero bits, where the
+/// whole array is a single voxel type and no index data is allocated, and
+/// doubles whenever the palette outgrows it.
+#[derive(Clone, Debug)]
+pub struct VoxelStorage {
+    len: usize,
+    palette: Vec<Voxel>,
+    bits_per_index: u32,
+    data: Vec<u64>,
+}
+
+impl VoxelStorage {
+    /// Creates storage for `len` voxels, all set to `voxel`.
+    pub fn new(len: usize, voxel: Voxel) -> Self {
+        Self {
+            len,
+            palette: vec![voxel],
+            bits_per_index: 0,
+            data: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the voxel filling the whole storage, if the palette only holds one.
+    pub fn uniform_voxel(&self) -> Option<Voxel> {
+        match self.palette.as_slice() {
+            [voxel] => Some(*voxel),
+            _ => None,
+        }
+    }
+
+    /// Approximate heap memory used by the palette and index data in bytes.
+    pub fn heap_size(&self) -> usize {
+        self.palette.capacity() * size_of::<Voxel>() + self.data.capacity() * size_of::<u64>()
+    }
+
+    pub fn get(&self, index: usize) -> Voxel {
+        debug_assert!(index < self.len);
+
+        if self.bits_per_index == 0 {
+            return self.palette[0];
+        }
+
+        self.palette[self.palette_index(index)]
+    }
+
+    pub fn set(&mut self, index: usize, voxel: Voxel) {
+        debug_assert!(index < self.len);
+
+        let palette_index = match self.palette.iter().position(|entry| *entry == voxel) {
+            Some(palette_index) => palette_index,
+            None => {
+                self.palette.push(voxel);
+
+                let required_bits = Self::required_bits(self.palette.len());
+                if required_bits > self.bits_per_index {
+                    self.resize(required_bits);
+                }
+
+                self.palette.len() - 1
+            }
+        };
+
+        if self.bits_per_index > 0 {
+            self.set_palette_index(index, palette_index);
+        }
+    }
+
+    /// Every voxel type that may be stored, including ones no longer used.
+    pub fn palette(&self) -> &[Voxel] {
+        &self.palette
+    }
+
+    /// Copies the voxels from `start` on into `out`, shifting through each word
+    /// of index data instead of locating every voxel on its own.
+    pub fn copy_to(&self, start: usize, out: &mut [Voxel]) {
+        debug_assert!(start + out.len() <= self.len);
+
+        if self.bits_per_index == 0 {
+            out.fill(self.palette[0]);
+            return;
+        }
+
+        let mask = (1u64 << self.bits_per_index) - 1;
+        let (mut word, shift) = self.locate(start);
+        let mut bits = self.data[word] >> shift;
+        let mut remaining = (u64::BITS - shift) / self.bits_per_index;
+
+        for voxel in out {
+            if remaining == 0 {
+                word += 1;
+                bits = self.data[word];
+                remaining = u64::BITS / self.bits_per_index;
+            }
+
+            *voxel = self.palette[(bits & mask) as usize];
+            bits >>= self.bits_per_index;
+            remaining -= 1;
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Voxel> + '_ {
+        (0..self.len).map(|index| self.get(index))
+    }
+
+    /// Removes palette entries that are no longer referenced and shrinks the
+    /// index width to match.
+    pub fn compact(&mut self) {
+        if self.bits_per_index == 0 {
+            return;
+        }
+
+        let mut used = vec![false; self.palette.len()];
+        for index in 0..self.len {
+            used[self.palette_index(index)] = true;
+        }
+
+        if used.iter().all(|used| *used) {
+            return;
+        }
+
+        let mut compacted = Self::new(self.len, self.get(0));
+        for index in 0..self.len {
+            compacted.set(index, self.get(index));
+        }
+
+        *self = compacted;
+    }
+
+    /// Writes the palette and packed indices, in little endian.
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&(self.palette.len() as u16).to_le_bytes())?;
+        for voxel in &self.palette {
+            writer.write_all(&[voxel.id()])?;
+        }
+
+        writer.write_all(&[self.bits_per_index as u8])?;
+        for word in &self.data {
+            writer.write_all(&word.to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads storage for `len` voxels previously written with [`Self::write_to`].
+    pub fn read_from(reader: &mut impl Read, len: usize) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        let mut palette_len = [0; 2];
+        reader.read_exact(&mut palette_len)?;
+        let palette_len = u16::from_le_bytes(palette_len) as usize;
+
+        let mut ids = vec![0; palette_len];
+        reader.read_exact(&mut ids)?;
+        let palette = ids
+            .into_iter()
+            .map(|id| Voxel::from_id(id).ok_or_else(|| invalid("unknown voxel id")))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let mut bits_per_index = [0; 1];
+        reader.read_exact(&mut bits_per_index)?;
+        let bits_per_index = bits_per_index[0] as u32;
+
+        if palette.is_empty() || bits_per_index != Self::required_bits(palette.len()) {
+            return Err(invalid("palette doesn't match index width"));
+        }
+
+        let word_count = match bits_per_index {
+            0 => 0,
+            bits => len.div_ceil((u64::BITS / bits) as usize),
+        };
+        let mut data = Vec::with_capacity(word_count);
+        for _ in 0..word_count {
+            let mut word = [0; 8];
+            reader.read_exact(&mut word)?;
+            data.push(u64::from_le_bytes(word));
+        }
+
+        let storage = Self {
+            len,
+            palette,
+            bits_per_index,
+            data,
+        };
+
+        if storage.bits_per_index > 0
+            && (0..len).any(|index| storage.palette_index(index) >= storage.palette.len())
+        {
+            return Err(invalid("voxel index outside of the palette"));
+        }
+
+        Ok(storage)
+    }
+
+    /// Smallest power of two bit width able to index `palette_len` entries.
+    fn required_bits(palette_len: usize) -> u32 {
+        match palette_len {
+            0..=1 => 0,
+            _ => (usize::BITS - (palette_len - 1).leading_zeros()).next_power_of_two(),
+        }
+    }
+
+    /// Word holding `index`, and the bit offset of the index inside it.
+    ///
+    /// Bit widths are powers of two, so indices never straddle two words and
+    /// everything reduces to shifts and masks.
+    fn locate(&self, index: usize) -> (usize, u32) {
+        let width_log2 = self.bits_per_index.trailing_zeros();
+        let per_word_log2 = u64::BITS.trailing_zeros() - width_log2;
+        let in_word = index & ((1 << per_word_log2) - 1);
+
+        (index >> per_word_log2, (in_word as u32) << width_log2)
+    }
+
+    fn palette_index(&self, index: usize) -> usize {
+        let (word, shift) = self.locate(index);
+        let mask = (1u64 << self.bits_per_index) - 1;
+
+        ((self.data[word] >> shift) & mask) as usize
+    }
+
+    fn set_palette_index(&mut self, index: usize, palette_index: usize) {
+        let (word, shift) = self.locate(index);
+        let mask = (1u64 << self.bits_per_index) - 1;
+
+        let word = &mut self.data[word];
+        *word = (*word & !(mask << shift)) | ((palette_index as u64) << shift);
+    }
+
+    /// Repacks the index data with a new bit width.
+    fn resize(&mut self, bits_per_index: u32) {
+        let old = std::mem::replace(
+            self,
+            Self {
+                len: self.len,
+                palette: Vec::new(),
+                bits_per_index,
+                data: vec![0; self.len.div_ceil((u64::BITS / bits_per_index) as usize)],
+            },
+        );
+
+        // Palette indices are kept as they were, only their width changes.
+        if old.bits_per_index > 0 {
+            for index in 0..self.len {
+                self.set_palette_index(index, old.palette_index(index));
+            }
+        }
+
+        self.palette = old.palette;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEN: usize = 32 * 32 * 32;
+
+    fn round_trip(storage: &VoxelStorage) -> VoxelStorage {
+        let mut bytes = Vec::new();
+        storage.write_to(&mut bytes).unwrap();
+
+        VoxelStorage::read_from(&mut bytes.as_slice(), storage.len()).unwrap()
+    }
+
+    #[test]
+    fn uniform_storage_round_trips() {
+        let storage = VoxelStorage::new(LEN, Voxel::Stone);
+        let read = round_trip(&storage);
+
+        assert_eq!(read.uniform_voxel(), Some(Voxel::Stone));
+        assert_eq!(read.heap_size(), storage.heap_size());
+    }
+
+    #[test]
+    fn mixed_storage_round_trips_at_every_index_width() {
+        // Palettes of 2, 3, 5 and 17 entries take 1, 2, 4 and 8 bits
+        for palette_len in [2, 3, 5, 17] {
+            let mut storage = VoxelStorage::new(LEN, Voxel::Air);
+            for index in 0..LEN {
+                storage.set(index, Voxel::ALL[index * 31 % palette_len]);
+            }
+
+            let read = round_trip(&storage);
+            assert_eq!(read.palette(), storage.palette());
+            assert!(read.iter().eq(storage.iter()), "palette of {palette_len}");
+        }
+    }
+
+    #[test]
+    fn copies_match_single_voxels() {
+        for palette_len in [1, 2, 3, 5, 17] {
+            let mut storage = VoxelStorage::new(LEN, Voxel::Air);
+            for index in 0..LEN {
+                storage.set(index, Voxel::ALL[index * 7 % palette_len]);
+            }
+
+            for (start, len) in [(0, LEN), (32, 32), (5, 100), (LEN - 3, 3)] {
+                let mut copy = vec![Voxel::Air; len];
+                storage.copy_to(start, &mut copy);
+                assert!(
+                    copy.iter()
+                        .copied()
+                        .eq((start..start + len).map(|i| storage.get(i)))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn compacted_storage_round_trips() {
+        let mut storage = VoxelStorage::new(LEN, Voxel::Air);
+        for index in 0..LEN {
+            storage.set(index, Voxel::ALL[index % 5]);
+        }
+        for index in 0..LEN {
+            storage.set(index, Voxel::ALL[index % 2]);
+        }
+        storage.compact();
+
+        assert_eq!(storage.palette().len(), 2);
+        assert!(round_trip(&storage).iter().eq(storage.iter()));
+    }
+
+    #[test]
+    fn corrupt_storage_is_rejected() {
+        let mut storage = VoxelStorage::new(LEN, Voxel::Air);
+        storage.set(0, Voxel::Stone);
+        storage.set(1, Voxel::Dirt);
+
+        let mut bytes = Vec::new();
+        storage.write_to(&mut bytes).unwrap();
+
+        // Index width that doesn't match the palette
+        let mut wrong_width = bytes.clone();
+        wrong_width[5] = 8;
+        assert!(VoxelStorage::read_from(&mut wrong_width.as_slice(), LEN).is_err());
+
+        // Unknown voxel id
+        let mut unknown_voxel = bytes.clone();
+        unknown_voxel[2] = u8::MAX;
+        assert!(VoxelStorage::read_from(&mut unknown_voxel.as_slice(), LEN).is_err());
+
+        // Index pointing past the palette, which has 3 of the 4 entries 2
+        // bits can address
+        let mut outside_palette = bytes.clone();
+        outside_palette[6] = 0b11;
+        assert!(VoxelStorage::read_from(&mut outside_palette.as_slice(), LEN).is_err());
+
+        // Truncated index data
+        assert!(VoxelStorage::read_from(&mut &bytes[..bytes.len() - 1], LEN).is_err());
+    }
+}
@@ -1,137 +1,234 @@
 use std::{
     collections::VecDeque,
-    sync::{Arc, RwLock, RwLockReadGuard},
+    path::{self, Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
 use bevy::{
-    platform::collections::HashMap,
+    platform::collections::{HashMap, HashSet},
     prelude::*,
-    tasks::{AsyncComputeTaskPool, futures_lite::future},
+    tasks::AsyncComputeTaskPool,
 };
 
 use crate::{
-    camera::PlayerCamera,
-    chunk::{Chunk, ChunkData, ChunkTask, ChunkThread, NeedsDespawn, NeedsMesh},
-    terrain::TerrainGenerator,
+    camera::{PlayerCamera, setup_camera},
+    chunk::{
+        Chunk, ChunkData, ChunkState, ChunkTask, ChunkTaskMetrics, ChunkThread, NeedsDespawn,
+        NeedsMesh,
+    },
+    chunk_queue::{ChunkQueue, queue_chunks},
+    light,
+    manifest::{WorldManifest, restore_camera, save_manifest_on_exit},
+    mesher::MeshingAlgorithm,
+    region::ChunkStorage,
+    terrain::{TerrainGenerator, TerrainSettings},
+    voxel::{RenderClass, Voxel},
 };
 
+/// Loads, generates and saves the world described by the [`WorldConfig`].
+///
+/// Unless a [`WorldManifest`] was inserted before, the plugin opens the one
+/// in the config's directory, or creates the world if it has none yet.
 pub struct WorldPlugin;
 
 impl Plugin for WorldPlugin {
     fn build(&self, app: &mut App) {
+        app.init_resource::<WorldConfig>();
+
+        if !app.world().contains_resource::<WorldManifest>() {
+            let mut world_config = app.world_mut().resource_mut::<WorldConfig>();
+            let world_manifest = WorldManifest::open_or_create(&mut world_config)
+                .unwrap_or_else(|err| panic!("failed to open world: {err}"));
+            app.insert_resource(world_manifest);
+        }
+
         app.init_resource::<WorldManager>()
             .init_resource::<WorldManagerInsertBuffer>()
             .init_resource::<WorldManagerUpdateBuffer>()
             .init_resource::<WorldManagerDespawnBuffer>()
+            .init_resource::<MeshingAlgorithm>()
+            .init_resource::<ChunkLoadingSettings>()
+            .init_resource::<ChunkQueue>()
+            .init_resource::<ChunkTaskMetrics>()
             .add_systems(PreStartup, setup)
+            .add_systems(Startup, restore_camera.after(setup_camera))
             .add_systems(
                 PreUpdate,
                 (
-                    ((spawn_chunks, tag_chunk_despawn).chain(), remesh_chunks).chain(),
+                    (
+                        (spawn_chunks, tag_chunk_despawn, update_meshed_chunks).chain(),
+                        queue_edited_chunks,
+                        queue_chunks,
+                        remesh_chunks,
+                    )
+                        .chain(),
                     (despawn_deleted_chunks, flush_chunk_buffers).chain(),
                 )
                     .chain(),
             )
-            .add_systems(Update, spawn_meshes);
+            .add_systems(Update, spawn_meshes)
+            .add_systems(Last, (save_chunks_on_exit, save_manifest_on_exit));
     }
 }
 
-fn setup(mut commands: Commands) {
+fn setup(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    world_config: Res<WorldConfig>,
+    world_manifest: Res<WorldManifest>,
+) {
+    info!(
+        "Opening world {} with seed {}",
+        world_config.directory.display(),
+        world_config.seed
+    );
+
+    if world_manifest.generator_version != TerrainGenerator::VERSION {
+        warn!(
+            "World was created with generator version {}, new chunks are generated with version {}",
+            world_manifest.generator_version,
+            TerrainGenerator::VERSION
+        );
+    }
+
+    commands.insert_resource(SharedTerrainGenerator(Arc::new(TerrainGenerator::new(
+        world_config.seed,
+        world_config.terrain.clone(),
+    ))));
+
+    commands.insert_resource(ChunkStorage::new(world_config.directory.join("regions")));
+
+    commands.insert_resource(ChunkMaterials {
+        opaque: materials.add(StandardMaterial {
+            base_color: Color::WHITE,
+            unlit: false, // Enable lighting for better visuals
+            ..default()
+        }),
+        cutout: materials.add(StandardMaterial {
+            base_color: Color::WHITE,
+            alpha_mode: AlphaMode::Mask(0.5),
+            ..default()
+        }),
+        translucent: materials.add(StandardMaterial {
+            base_color: Color::WHITE,
+            alpha_mode: AlphaMode::Blend,
+            // Water surfaces are seen from below as well
+            cull_mode: None,
+            ..default()
+        }),
+    });
+
     commands.spawn((WorldEntity, Visibility::default(), Transform::default()));
 }
 
+/// Spawns the chunks missing within the generation distances.
+///
+/// Chunks only go missing by leaving those distances, so the view box is
+/// only scanned again once the camera enters another chunk or the loading
+/// settings change.
 fn spawn_chunks(
     mut commands: Commands,
     mut spawn_buffer: ResMut<WorldManagerInsertBuffer>,
     world_manager: Res<WorldManager>,
+    loading_settings: Res<ChunkLoadingSettings>,
     player_camera: Query<&Transform, With<PlayerCamera>>,
     world_entity: Query<Entity, With<WorldEntity>>,
+    mut scanned_from: Local<Option<IVec3>>,
 ) {
     let world_entity = world_entity.single().unwrap();
-    let camera = player_camera.single().unwrap();
-    let cam_pos = camera.translation.as_ivec3();
-
-    let render_distance: i32 = 8;
-    let render_distance_squared = render_distance.pow(2);
-    let radius = render_distance / 2;
+    let cam_chunk_pos = camera_chunk_pos(player_camera.single().unwrap());
 
-    let mut chunks_deque: VecDeque<IVec3> =
-        VecDeque::with_capacity(render_distance_squared as usize);
-
-    let cam_chunk_pos = cam_pos / ChunkData::SIZE as i32;
-    for x in -radius..=radius {
-        for y in -radius..=radius {
-            for z in -radius..=radius {
-                chunks_deque.push_back(cam_chunk_pos + IVec3::new(x, y, z));
-            }
-        }
+    if *scanned_from == Some(cam_chunk_pos) && !loading_settings.is_changed() {
+        return;
     }
+    *scanned_from = Some(cam_chunk_pos);
 
-    while let Some(chunk_position) = chunks_deque.pop_front() {
-        // check if chunk is in range and queue if needed
+    let horizontal = loading_settings.horizontal_distance + loading_settings.generation_margin;
+    let vertical = loading_settings.vertical_distance + loading_settings.generation_margin;
 
-        if chunk_position.distance_squared(cam_chunk_pos) > render_distance_squared {
-            continue;
-        }
+    for x in -horizontal..=horizontal {
+        for y in -vertical..=vertical {
+            for z in -horizontal..=horizontal {
+                let offset = IVec3::new(x, y, z);
+                if !loading_settings.is_generated(offset) {
+                    continue;
+                }
 
-        let read_lock = world_manager.get_lock();
-        let has_chunk = world_manager.contains_chunk(&chunk_position, &read_lock);
+                let chunk_position = cam_chunk_pos + offset;
+                if world_manager.contains_chunk(&chunk_position) {
+                    continue;
+                }
 
-        if !has_chunk {
-            // queue chunk to load
-            let chunk_entity = commands.spawn(NeedsMesh).id();
-            commands.entity(world_entity).add_child(chunk_entity);
+                // queue chunk to load
+                let chunk_entity = commands.spawn((ChunkState::Queued, NeedsMesh)).id();
+                commands.entity(world_entity).add_child(chunk_entity);
 
-            let chunk = Chunk::new(chunk_position, chunk_entity);
+                let chunk = Chunk::new(chunk_position, chunk_entity);
 
-            spawn_buffer.push((
-                chunk_position,
-                ChunkData::with_entity(chunk_position, chunk.entity),
-            ));
+                spawn_buffer.push((
+                    chunk_position,
+                    ChunkData::with_entity(chunk_position, chunk.entity),
+                ));
 
-            commands.entity(chunk.entity).try_insert((
-                chunk,
-                Transform::from_translation(
-                    chunk_position.as_vec3() * ChunkData::SIZE as f32 - 1.0,
-                ),
-            ));
+                commands.entity(chunk.entity).try_insert((
+                    chunk,
+                    Transform::from_translation(chunk_position.as_vec3() * ChunkData::SIZE as f32),
+                    Visibility::default(),
+                ));
+            }
         }
     }
 }
 
 fn tag_chunk_despawn(
     mut commands: Commands,
-    all_chunks: Query<(&Chunk, Option<&ViewVisibility>)>,
+    all_chunks: Query<&Chunk>,
+    loading_settings: Res<ChunkLoadingSettings>,
     player_camera: Query<&Transform, With<PlayerCamera>>,
 ) {
-    let camera = player_camera.single().unwrap();
-    let cam_pos = camera.translation.as_ivec3();
-
-    let render_distance: i32 = 8;
-    let render_distance_squared = render_distance.pow(2);
+    let cam_chunk_pos = camera_chunk_pos(player_camera.single().unwrap());
 
-    let cam_chunk_pos = cam_pos / ChunkData::SIZE as i32;
+    for chunk in all_chunks.iter() {
+        if !loading_settings.is_kept(chunk.position - cam_chunk_pos) {
+            commands.entity(chunk.entity).try_insert(NeedsDespawn);
+        }
+    }
+}
 
-    let chunk_to_remove = {
-        let mut remove = Vec::with_capacity(100);
+/// Meshes generated chunks once they come within the view distances and
+/// their neighbors are generated, and drops the meshes of chunks that left
+/// them.
+fn update_meshed_chunks(
+    mut commands: Commands,
+    world_manager: Res<WorldManager>,
+    loading_settings: Res<ChunkLoadingSettings>,
+    player_camera: Query<&Transform, With<PlayerCamera>>,
+    mut chunks: Query<(Entity, &Chunk, &mut ChunkState), Without<NeedsMesh>>,
+) {
+    let cam_chunk_pos = camera_chunk_pos(player_camera.single().unwrap());
 
-        // visibility is used when we want to only despawn when the user isnt looking at the chunk
-        for (chunk, _visibility) in all_chunks.iter() {
-            let dist_squared = chunk.position.distance_squared(cam_chunk_pos);
+    for (entity, chunk, mut state) in chunks.iter_mut() {
+        let in_view = loading_settings.is_meshed(chunk.position - cam_chunk_pos);
 
-            if dist_squared > render_distance_squared + 1 {
-                remove.push(chunk);
+        match *state {
+            ChunkState::Generated if in_view && world_manager.can_mesh(&chunk.position) => {
+                commands.entity(entity).try_insert(NeedsMesh);
+            }
+            ChunkState::Ready if !in_view => {
+                commands.entity(entity).despawn_related::<Children>();
+                *state = ChunkState::Generated;
             }
+            _ => {}
         }
-
-        remove
-    };
-
-    for chunk in chunk_to_remove {
-        commands.entity(chunk.entity).try_insert(NeedsDespawn);
     }
 }
 
+/// Chunk containing the camera.
+pub(crate) fn camera_chunk_pos(camera: &Transform) -> IVec3 {
+    WorldManager::world_to_chunk_pos(&camera.translation.floor().as_ivec3())
+}
+
 fn despawn_deleted_chunks(
     mut commands: Commands,
     mut despawn_buffer: ResMut<WorldManagerDespawnBuffer>,
@@ -139,10 +236,7 @@ fn despawn_deleted_chunks(
     deleted_chunks: Query<(Entity, &Chunk), With<NeedsDespawn>>,
 ) {
     for (entity, chunk) in deleted_chunks.iter() {
-        let read_lock = world_manager.get_lock();
-        let has_chunk = world_manager.contains_chunk(&chunk.position, &read_lock);
-
-        if has_chunk {
+        if world_manager.contains_chunk(&chunk.position) {
             commands.entity(entity).despawn();
             despawn_buffer.push(chunk.position);
         }
@@ -150,103 +244,422 @@ fn despawn_deleted_chunks(
 }
 
 fn flush_chunk_buffers(
-    world_manager: ResMut<WorldManager>,
+    mut world_manager: ResMut<WorldManager>,
+    chunk_storage: Res<ChunkStorage>,
+    terrain_generator: Res<SharedTerrainGenerator>,
     mut spawn_buffer: ResMut<WorldManagerInsertBuffer>,
     mut despawn_buffer: ResMut<WorldManagerDespawnBuffer>,
     mut update_buffer: ResMut<WorldManagerUpdateBuffer>,
 ) {
-    if let Ok(mut lock) = world_manager.chunks.try_write() {
-        for (position, chunk_data) in spawn_buffer.iter() {
-            lock.insert(*position, chunk_data.clone());
+    for (position, chunk_data) in spawn_buffer.drain(..) {
+        world_manager.chunks.insert(position, Arc::new(chunk_data));
+    }
+
+    for position in despawn_buffer.drain(..) {
+        if let Some(chunk_data) = world_manager.chunks.remove(&position)
+            && chunk_data.generated
+            && chunk_data.dirty
+        {
+            chunk_storage.save_chunk_async(&chunk_data);
+        }
+    }
+
+    // New chunks are lit as they are inserted, which floods into their
+    // neighbors and can't leave the main thread. Chunks that don't fit into
+    // the frame's budget wait for the next one, at least one goes in per frame.
+    let start = Instant::now();
+    let mut inserted = 0;
+
+    while inserted == 0 || start.elapsed() < LIGHT_TIME_BUDGET {
+        let Some((position, chunk_data)) = update_buffer.pop_front() else {
+            break;
+        };
+
+        // Despawned while waiting, possibly respawned by a newer entity
+        if world_manager
+            .get_chunk(&position)
+            .is_none_or(|chunk| chunk.entity() != chunk_data.entity())
+        {
+            continue;
+        }
+
+        world_manager.insert_generated(chunk_data, &terrain_generator);
+        inserted += 1;
+    }
+}
+
+/// Writes every modified chunk still loaded once the app is closing.
+fn save_chunks_on_exit(
+    mut exit_events: EventReader<AppExit>,
+    world_manager: Res<WorldManager>,
+    chunk_storage: Res<ChunkStorage>,
+) {
+    if exit_events.read().last().is_none() {
+        return;
+    }
+
+    chunk_storage.flush_pending();
+
+    let mut saved = 0;
+
+    for chunk_data in world_manager.chunks.values() {
+        if !chunk_data.generated || !chunk_data.dirty {
+            continue;
         }
-        spawn_buffer.clear();
 
-        for (position, chunk_data) in update_buffer.iter() {
-            lock.insert(*position, chunk_data.clone());
+        let mut voxels = chunk_data.voxels().clone();
+        voxels.compact();
+
+        match chunk_storage.save_chunk(chunk_data.position, &voxels) {
+            Ok(()) => saved += 1,
+            Err(err) => error!("Failed to save chunk {}: {err}", chunk_data.position),
+        }
+    }
+
+    info!("Saved {saved} modified chunks");
+}
+
+/// Queues a re-mesh for every chunk touched by voxel edits since last frame.
+///
+/// Chunks beyond the view distances are skipped, they are meshed once they
+/// come into view.
+fn queue_edited_chunks(
+    mut commands: Commands,
+    mut world_manager: ResMut<WorldManager>,
+    loading_settings: Res<ChunkLoadingSettings>,
+    player_camera: Query<&Transform, With<PlayerCamera>>,
+) {
+    if world_manager.pending_remesh.is_empty() {
+        return;
+    }
+
+    let cam_chunk_pos = camera_chunk_pos(player_camera.single().unwrap());
+    let pending_remesh = std::mem::take(&mut world_manager.pending_remesh);
+
+    for position in pending_remesh {
+        if !loading_settings.is_meshed(position - cam_chunk_pos) {
+            continue;
         }
-        update_buffer.clear();
 
-        for position in despawn_buffer.iter() {
-            lock.remove(position);
+        // Chunks still being generated are meshed once they are done
+        if let Some(chunk) = world_manager
+            .get_chunk(&position)
+            .filter(|chunk| chunk.generated)
+        {
+            commands.entity(chunk.entity()).try_insert(NeedsMesh);
         }
-        despawn_buffer.clear();
     }
 }
 
+/// Starts tasks for the most important queued chunks, as long as fewer than
+/// [`TASKS_PER_THREAD`] per thread of the task pool are running.
+///
+/// A chunk queued again while its previous task is still running gets a new
+/// task, which cancels the old one.
+#[allow(clippy::too_many_arguments)]
 fn remesh_chunks(
     mut commands: Commands,
     world_manager: Res<WorldManager>,
-    chunks: Query<(Entity, &Chunk), With<NeedsMesh>>,
+    terrain_generator: Res<SharedTerrainGenerator>,
+    chunk_storage: Res<ChunkStorage>,
+    meshing_algorithm: Res<MeshingAlgorithm>,
+    mut chunk_queue: ResMut<ChunkQueue>,
+    task_metrics: Res<ChunkTaskMetrics>,
+    mut chunks: Query<(&Chunk, &mut ChunkState, Has<NeedsMesh>, Has<ChunkThread>)>,
 ) {
     let thread_pool = AsyncComputeTaskPool::get();
 
-    for (entity, chunk) in chunks.iter() {
-        // load chunk mesh here
+    let in_flight = chunks.iter().filter(|(.., running)| *running).count();
+    let budget = (thread_pool.thread_num() * TASKS_PER_THREAD).saturating_sub(in_flight);
+
+    let mut started = 0;
+
+    while started < budget {
+        let Some((entity, _)) = chunk_queue.pop() else {
+            break;
+        };
+
+        // Chunks may have been despawned while waiting
+        let Ok((chunk, mut state, true, _)) = chunks.get_mut(entity) else {
+            continue;
+        };
+
+        let neighborhood = match *state {
+            ChunkState::Queued => {
+                *state = ChunkState::Generating;
+                None
+            }
+            // Meshed once generated anyway
+            ChunkState::Generating => {
+                commands.entity(entity).remove::<NeedsMesh>();
+                continue;
+            }
+            // Waits for the last neighbor to arrive, which queues it again
+            _ if !world_manager.can_mesh(&chunk.position) => {
+                commands.entity(entity).remove::<NeedsMesh>();
+                continue;
+            }
+            ChunkState::Generated | ChunkState::Meshing | ChunkState::Ready => {
+                *state = ChunkState::Meshing;
+                Some(world_manager.neighborhood(&chunk.position))
+            }
+        };
+        started += 1;
 
         let mut chunk_task = ChunkTask::new(chunk.position, entity);
-        let chunk_map = world_manager.get_map();
+        let algorithm = *meshing_algorithm;
+        let terrain_generator = terrain_generator.clone();
+        let chunk_storage = chunk_storage.clone();
 
+        let cancelled = chunk_task.cancel_flag();
         let thread = thread_pool.spawn(async move {
-            chunk_task.generate();
-            chunk_task.mesh(chunk_map);
+            if chunk_task.is_cancelled() {
+                return chunk_task;
+            }
+
+            // Fresh chunks have no light yet, they are meshed after being lit
+            match neighborhood {
+                Some(neighborhood) => chunk_task.mesh(&neighborhood, algorithm),
+                None => chunk_task.load_or_generate(&terrain_generator, &chunk_storage),
+            }
 
             chunk_task
         });
 
         commands
             .entity(entity)
-            .try_insert(ChunkThread { thread })
+            .try_insert(ChunkThread::new(thread, cancelled, task_metrics.clone()))
             .remove::<NeedsMesh>();
     }
 }
 
 fn spawn_meshes(
     mut commands: Commands,
-    chunks: Query<(Entity, &mut ChunkThread, &Chunk, &Transform), Without<NeedsMesh>>,
+    chunks: Query<(Entity, &mut ChunkThread, &Chunk), Without<NeedsMesh>>,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    chunk_materials: Res<ChunkMaterials>,
     mut update_buffer: ResMut<WorldManagerUpdateBuffer>,
 ) {
-    for (entity, mut thread, chunk, transform) in chunks {
-        let result = future::block_on(future::poll_once(&mut thread.thread));
-
-        if result.is_none() {
-            return;
-        }
-
-        let chunk_task = result.unwrap();
+    for (entity, mut thread, chunk) in chunks {
+        let Some(chunk_task) = thread.poll() else {
+            continue;
+        };
 
-        if chunk_task.mesh.is_none() {
+        if chunk_task.newly_generated {
+            // Meshed once flushed into the world and lit
+            update_buffer.push_back((chunk.position, chunk_task.chunk_data));
             commands
                 .entity(chunk.entity)
-                .try_insert(NeedsMesh)
-                .remove::<ChunkThread>();
+                .remove::<ChunkThread>()
+                .try_insert(ChunkState::Generated);
             continue;
         }
 
-        let mesh_ref = meshes.add(chunk_task.mesh.unwrap());
+        // Every render class is drawn by a child of the chunk with the
+        // matching material, all of them replaced when the chunk is re-meshed.
+        // Chunks with nothing visible don't get any.
+        commands.entity(entity).despawn_related::<Children>();
 
-        commands.entity(entity).try_insert((
-            *transform,
-            Mesh3d(mesh_ref),
-            MeshMaterial3d(materials.add(StandardMaterial {
-                base_color: Color::WHITE,
-                unlit: false, // Enable lighting for better visuals
-                ..default()
-            })),
-        ));
+        if let Some(mesh) = chunk_task.mesh {
+            for (render_class, mesh) in mesh.into_meshes() {
+                commands.entity(entity).with_child((
+                    ChunkMeshPart(render_class),
+                    Mesh3d(meshes.add(mesh)),
+                    MeshMaterial3d(chunk_materials.get(render_class)),
+                ));
+            }
+        }
+
+        commands
+            .entity(chunk.entity)
+            .remove::<ChunkThread>()
+            .try_insert(ChunkState::Ready);
+    }
+}
+
+/// Chunk tasks kept running per thread of the [`AsyncComputeTaskPool`], enough
+/// to keep every thread busy without flooding the pool when many chunks are
+/// requested at once.
+const TASKS_PER_THREAD: usize = 2;
+
+/// Time per frame spent lighting newly generated chunks, see
+/// `flush_chunk_buffers`.
+const LIGHT_TIME_BUDGET: Duration = Duration::from_millis(4);
+
+/// Distances in chunks around the camera within which chunks are loaded.
+///
+/// Chunks are generated a `generation_margin` beyond the view distances, so
+/// all 26 neighbors exist by the time they are meshed, and only unloaded once
+/// they are another `unload_margin` further away, so moving back and forth
+/// across a border doesn't reload the same chunks. Changes take effect on the
+/// next frame.
+#[derive(Resource, Clone, Debug)]
+pub struct ChunkLoadingSettings {
+    /// Distance along the ground up to which chunks are meshed.
+    pub horizontal_distance: i32,
+    /// Distance above and below the camera up to which chunks are meshed.
+    pub vertical_distance: i32,
+    pub generation_margin: i32,
+    pub unload_margin: i32,
+}
+
+impl Default for ChunkLoadingSettings {
+    fn default() -> Self {
+        Self {
+            horizontal_distance: 8,
+            vertical_distance: 4,
+            generation_margin: 1,
+            unload_margin: 2,
+        }
+    }
+}
+
+impl ChunkLoadingSettings {
+    /// Whether the chunk at `offset` from the camera's chunk is meshed.
+    pub fn is_meshed(&self, offset: IVec3) -> bool {
+        self.is_within(offset, 0)
+    }
+
+    /// Whether the chunk at `offset` from the camera's chunk is generated.
+    pub fn is_generated(&self, offset: IVec3) -> bool {
+        self.is_within(offset, self.generation_margin)
+    }
+
+    /// Whether the chunk at `offset` from the camera's chunk stays loaded.
+    pub fn is_kept(&self, offset: IVec3) -> bool {
+        self.is_within(offset, self.generation_margin + self.unload_margin)
+    }
+
+    /// Whether `offset` lies within `margin` chunks along every axis of the
+    /// cylinder of the view distances.
+    ///
+    /// Widening the cylinder by whole chunks per axis rather than radially
+    /// keeps the diagonal neighbors of its chunks inside the margin, too.
+    fn is_within(&self, offset: IVec3, margin: i32) -> bool {
+        let nearest = offset.xz()
+            - offset
+                .xz()
+                .clamp(IVec2::splat(-margin), IVec2::splat(margin));
+
+        nearest.length_squared() <= self.horizontal_distance * self.horizontal_distance
+            && offset.y.abs() <= self.vertical_distance + margin
+    }
+}
+
+/// Directory holding one sub-directory per named world.
+pub const SAVES_DIRECTORY: &str = "saves";
+
+/// Settings a world is generated from.
+#[derive(Resource, Clone, Debug)]
+pub struct WorldConfig {
+    pub seed: u32,
+    pub terrain: TerrainSettings,
+    /// Directory the world manifest and modified chunks are saved to.
+    pub directory: PathBuf,
+}
+
+impl Default for WorldConfig {
+    fn default() -> Self {
+        Self {
+            seed: 12345,
+            terrain: TerrainSettings::default(),
+            directory: PathBuf::from(SAVES_DIRECTORY).join("default"),
+        }
+    }
+}
+
+impl WorldConfig {
+    /// Builds a config from command line arguments, falling back to the
+    /// defaults for anything not given.
+    ///
+    /// Supported options are `--world <name>`, `--seed <u32>`,
+    /// `--base-height <f64>`, `--height-amplitude <f64>`, `--height-scale <f64>`,
+    /// `--biome-scale <f64>`, `--continent-scale <f64>`, `--cheese-threshold <f64>`,
+    /// `--spaghetti-width <f64>`, `--cave-floor <f64>` and `--sea-level <f64>`. The seed and terrain
+    /// options only apply when the world is first created.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut config = Self::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("missing value for `{arg}`"))
+            };
+
+            match arg.as_str() {
+                "--world" => {
+                    config.directory = PathBuf::from(SAVES_DIRECTORY).join(world_name(value()?)?)
+                }
+                "--seed" => config.seed = parse_arg(&arg, value()?)?,
+                "--base-height" => config.terrain.base_height = parse_arg(&arg, value()?)?,
+                "--height-amplitude" => {
+                    config.terrain.height_amplitude = parse_arg(&arg, value()?)?
+                }
+                "--height-scale" => config.terrain.height_scale = parse_arg(&arg, value()?)?,
+                "--biome-scale" => config.terrain.biome_scale = parse_arg(&arg, value()?)?,
+                "--continent-scale" => config.terrain.continent_scale = parse_arg(&arg, value()?)?,
+                "--cheese-threshold" => {
+                    config.terrain.cheese_threshold = parse_arg(&arg, value()?)?
+                }
+                "--spaghetti-width" => config.terrain.spaghetti_width = parse_arg(&arg, value()?)?,
+                "--cave-floor" => config.terrain.cave_floor = parse_arg(&arg, value()?)?,
+                "--sea-level" => config.terrain.sea_level = parse_arg(&arg, value()?)?,
+                _ => return Err(format!("unknown argument `{arg}`")),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+/// Checks that a world name is a single plain path component, so the save
+/// directory can't end up outside of [`SAVES_DIRECTORY`].
+fn world_name(value: String) -> Result<String, String> {
+    let mut components = Path::new(&value).components();
+    match (components.next(), components.next()) {
+        (Some(path::Component::Normal(name)), None) if name == value.as_str() => Ok(value),
+        _ => Err(format!("invalid world name `{value}`")),
+    }
+}
+
+fn parse_arg<T: std::str::FromStr>(name: &str, value: String) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value `{value}` for `{name}`"))
+}
+
+/// Child of a chunk entity drawing the chunk's faces of one render class.
+#[derive(Component)]
+pub struct ChunkMeshPart(pub RenderClass);
 
-        update_buffer.push((chunk.position, chunk_task.chunk_data));
+/// Materials shared by every chunk mesh, one per [`RenderClass`].
+#[derive(Resource)]
+pub struct ChunkMaterials {
+    pub opaque: Handle<StandardMaterial>,
+    pub cutout: Handle<StandardMaterial>,
+    pub translucent: Handle<StandardMaterial>,
+}
 
-        commands.entity(chunk.entity).remove::<ChunkThread>();
+impl ChunkMaterials {
+    pub fn get(&self, render_class: RenderClass) -> Handle<StandardMaterial> {
+        match render_class {
+            RenderClass::Opaque => self.opaque.clone(),
+            RenderClass::Cutout => self.cutout.clone(),
+            RenderClass::Translucent => self.translucent.clone(),
+        }
     }
 }
 
+/// Terrain generator shared by every chunk task, built from the [`WorldConfig`].
+#[derive(Resource, Clone, Deref)]
+pub struct SharedTerrainGenerator(Arc<TerrainGenerator>);
+
 #[derive(Resource, Deref, DerefMut, Default)]
 pub struct WorldManagerInsertBuffer(Vec<(IVec3, ChunkData)>);
 
 #[derive(Resource, Deref, DerefMut, Default)]
-pub struct WorldManagerUpdateBuffer(Vec<(IVec3, ChunkData)>);
+pub struct WorldManagerUpdateBuffer(VecDeque<(IVec3, ChunkData)>);
 
 #[derive(Resource, Deref, DerefMut, Default)]
 pub struct WorldManagerDespawnBuffer(Vec<IVec3>);
@@ -254,32 +667,96 @@ pub struct WorldManagerDespawnBuffer(Vec<IVec3>);
 #[derive(Component)]
 pub struct WorldEntity;
 
-pub type ChunkMap = HashMap<IVec3, ChunkData>;
+/// Loaded chunks by position.
+///
+/// Chunks are shared so tasks can keep working on a snapshot of a few of them
+/// while the world moves on. Writes go through [`Arc::make_mut`], which only
+/// copies a chunk while a snapshot still holds it.
+pub type ChunkMap = HashMap<IVec3, Arc<ChunkData>>;
 
 #[derive(Default, Resource)]
 pub struct WorldManager {
-    chunks: Arc<RwLock<ChunkMap>>,
+    chunks: ChunkMap,
+    /// Chunks whose mesh is out of date after voxel edits.
+    pending_remesh: HashSet<IVec3>,
 }
 
 impl WorldManager {
-    pub fn get_lock(&self) -> std::sync::RwLockReadGuard<'_, HashMap<IVec3, ChunkData>> {
-        self.chunks.read().unwrap()
+    pub fn chunks(&self) -> &ChunkMap {
+        &self.chunks
     }
 
-    pub fn get_map(&self) -> Arc<RwLock<ChunkMap>> {
-        self.chunks.clone()
+    /// Snapshot of the chunk at `position` and the 26 chunks around it,
+    /// sharing their data with the world.
+    ///
+    /// Meshing only looks at a chunk and the voxels bordering it, so a task
+    /// can mesh from the snapshot while the world keeps being written.
+    pub fn neighborhood(&self, position: &IVec3) -> ChunkMap {
+        let mut neighborhood = ChunkMap::default();
+
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let neighbor_pos = *position + IVec3::new(x, y, z);
+                    if let Some(chunk) = self.chunks.get(&neighbor_pos) {
+                        neighborhood.insert(neighbor_pos, chunk.clone());
+                    }
+                }
+            }
+        }
+
+        neighborhood
+    }
+
+    pub fn contains_chunk(&self, position: &IVec3) -> bool {
+        self.chunks.contains_key(position)
     }
 
-    pub fn contains_chunk(&self, position: &IVec3, read_lock: &RwLockReadGuard<ChunkMap>) -> bool {
-        read_lock.contains_key(position)
+    /// Whether the chunk at `position` and the 26 chunks around it are
+    /// generated, so the faces along its borders are culled, and shaded with
+    /// the ambient occlusion and light of the voxels around them, correctly.
+    pub fn can_mesh(&self, position: &IVec3) -> bool {
+        (-1..=1).all(|x| {
+            (-1..=1).all(|y| {
+                (-1..=1).all(|z| {
+                    self.get_chunk(&(*position + IVec3::new(x, y, z)))
+                        .is_some_and(|chunk| chunk.generated)
+                })
+            })
+        })
+    }
+
+    /// Inserts a freshly generated chunk and lights it.
+    ///
+    /// New chunks are only meshed once they are lit, which also re-meshes the
+    /// neighbors their light spread into. The 26 chunks around it, which were
+    /// either waiting for it or meshed with air in its place, are re-meshed as
+    /// well.
+    pub(crate) fn insert_generated(
+        &mut self,
+        chunk_data: ChunkData,
+        terrain_generator: &TerrainGenerator,
+    ) {
+        let position = chunk_data.position;
+        self.chunks.insert(position, Arc::new(chunk_data));
+
+        self.pending_remesh.extend(light::light_new_chunk(
+            &mut self.chunks,
+            position,
+            terrain_generator,
+        ));
+
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    self.pending_remesh.insert(position + IVec3::new(x, y, z));
+                }
+            }
+        }
     }
 
-    pub fn get_chunk<'a>(
-        &self,
-        position: &IVec3,
-        read_lock: &'a RwLockReadGuard<ChunkMap>,
-    ) -> Option<&'a ChunkData> {
-        read_lock.get(position)
+    pub fn get_chunk(&self, position: &IVec3) -> Option<&ChunkData> {
+        self.chunks.get(position).map(Arc::as_ref)
     }
 
     pub fn world_to_chunk_pos(world_pos: &IVec3) -> IVec3 {
@@ -298,52 +775,253 @@ impl WorldManager {
         )
     }
 
-    // pub fn get_voxel(&self, world_pos: &IVec3) -> Voxel {
-    //     let chunk_pos = Self::world_to_chunk_pos(world_pos);
-    //     let local_pos = Self::world_to_local_pos(world_pos);
-    //
-    //     let read_lock = self.get_lock();
-    //     if let Some(chunk) = self.get_chunk(&chunk_pos, &read_lock) {
-    //         chunk.get_voxel(
-    //             local_pos.x as usize,
-    //             local_pos.y as usize,
-    //             local_pos.z as usize,
-    //         )
-    //     } else {
-    //         Voxel::Air // No chunk exists here
-    //     }
-    // }
-
-    // pub fn generate_world(&mut self, size: i32, seed: u32) {
-    //     let terrain_generator = TerrainGenerator::new(seed);
-    //
-    //     for x in 0..size {
-    //         for z in 0..size {
-    //             let chunk_pos = IVec3::new(x, 0, z);
-    //
-    //             let chunk = self.generate_chunk(chunk_pos, &terrain_generator);
-    //
-    //             // self.insert(chunk_pos, chunk);
-    //         }
-    //     }
-    // }
-    //
-    // fn generate_chunk(&self, chunk_pos: IVec3, terrain_generator: &TerrainGenerator) -> ChunkData {
-    //     let mut chunk = ChunkData::new(chunk_pos);
-    //
-    //     for x in 0..ChunkData::SIZE {
-    //         for z in 0..ChunkData::SIZE {
-    //             for y in 0..ChunkData::SIZE {
-    //                 let voxel = terrain_generator.get_voxel(IVec3::new(
-    //                     (chunk_pos.x * ChunkData::SIZE as i32) + x as i32,
-    //                     (chunk_pos.y * ChunkData::SIZE as i32) + y as i32,
-    //                     (chunk_pos.z * ChunkData::SIZE as i32) + z as i32,
-    //                 ));
-    //                 chunk.set_voxel(voxel, x, y, z);
-    //             }
-    //         }
-    //     }
-    //
-    //     chunk
-    // }
+    /// Returns the voxel at `world_pos`, or `None` if its chunk isn't loaded.
+    pub fn get_voxel(&self, world_pos: IVec3) -> Option<Voxel> {
+        let chunk_pos = Self::world_to_chunk_pos(&world_pos);
+        let local_pos = Self::world_to_local_pos(&world_pos);
+
+        let chunk = self.get_chunk(&chunk_pos).filter(|chunk| chunk.generated)?;
+
+        Some(chunk.get_voxel(
+            local_pos.x as usize,
+            local_pos.y as usize,
+            local_pos.z as usize,
+        ))
+    }
+
+    /// Sets the voxel at `world_pos` and queues the affected chunks for
+    /// re-meshing.
+    ///
+    /// Returns `false` without doing anything when the chunk isn't loaded yet.
+    pub fn set_voxel(&mut self, world_pos: IVec3, voxel: Voxel) -> bool {
+        self.set_voxels([(world_pos, voxel)]) == 1
+    }
+
+    /// Applies several voxel edits, updating the light around all of them at
+    /// once.
+    ///
+    /// Returns how many edits were applied; edits in chunks that aren't loaded
+    /// are skipped.
+    pub fn set_voxels(&mut self, edits: impl IntoIterator<Item = (IVec3, Voxel)>) -> usize {
+        let mut edited = Vec::new();
+
+        for (world_pos, voxel) in edits {
+            let chunk_pos = Self::world_to_chunk_pos(&world_pos);
+            let local_pos = Self::world_to_local_pos(&world_pos);
+
+            let Some(chunk) = self
+                .chunks
+                .get_mut(&chunk_pos)
+                .filter(|chunk| chunk.generated)
+            else {
+                continue;
+            };
+
+            let (x, y, z) = (
+                local_pos.x as usize,
+                local_pos.y as usize,
+                local_pos.z as usize,
+            );
+            let previous = chunk.get_voxel(x, y, z);
+            if previous == voxel {
+                continue;
+            }
+
+            Arc::make_mut(chunk).set_voxel(voxel, x, y, z);
+            edited.push(world_pos);
+
+            self.pending_remesh.insert(chunk_pos);
+
+            // Anything but swapping one opaque voxel for another can expose or
+            // hide faces and change the ambient occlusion of every voxel around
+            // it, which may belong to neighboring chunks.
+            if !(previous.is_opaque() && voxel.is_opaque()) {
+                for x in -1..=1 {
+                    for y in -1..=1 {
+                        for z in -1..=1 {
+                            let neighbor_pos = world_pos + IVec3::new(x, y, z);
+                            self.pending_remesh
+                                .insert(Self::world_to_chunk_pos(&neighbor_pos));
+                        }
+                    }
+                }
+            }
+        }
+
+        let applied = edited.len();
+        self.pending_remesh
+            .extend(light::update_light(&mut self.chunks, edited));
+
+        applied
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunk::FaceDirection, light::LightLevel, manifest::MANIFEST_FILE, mesher::QuadSink,
+        region::tests::TemporaryDirectory,
+    };
+
+    /// Ambient occlusion of the faces of a mesh by voxel and direction.
+    #[derive(Default, PartialEq, Debug)]
+    struct FaceOcclusion(HashMap<(IVec3, FaceDirection), [u8; 4]>);
+
+    impl QuadSink for FaceOcclusion {
+        fn add_quad(
+            &mut self,
+            position: Vec3,
+            _size: Vec3,
+            face_direction: FaceDirection,
+            _voxel: Voxel,
+            ao: [u8; 4],
+            _light: LightLevel,
+        ) {
+            self.0.insert((position.as_ivec3(), face_direction), ao);
+        }
+    }
+
+    fn face_occlusion(world_manager: &WorldManager, position: IVec3) -> FaceOcclusion {
+        let mut faces = FaceOcclusion::default();
+        world_manager.get_chunk(&position).unwrap().generate_quads(
+            &world_manager.neighborhood(&position),
+            MeshingAlgorithm::FaceCulling,
+            &mut faces,
+        );
+        faces
+    }
+
+    #[test]
+    fn chunks_are_meshed_again_once_diagonal_neighbors_arrive() {
+        // Stone in the corner of the origin chunk, with another one in the
+        // diagonal neighbor shading the top of the first
+        let generated = |position: IVec3| {
+            let mut chunk_data = ChunkData::new(position);
+            chunk_data.generated = true;
+            if position == IVec3::ZERO {
+                chunk_data.set_voxel(Voxel::Stone, 31, 0, 31);
+            } else if position == IVec3::new(1, 0, 1) {
+                chunk_data.set_voxel(Voxel::Stone, 0, 1, 0);
+            }
+            chunk_data
+        };
+
+        let diagonal = IVec3::new(1, 0, 1);
+        let mut world_manager = WorldManager::default();
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let position = IVec3::new(x, y, z);
+                    if position != diagonal {
+                        world_manager
+                            .insert_generated(generated(position), &TerrainGenerator::sunken());
+                    }
+                }
+            }
+        }
+
+        assert!(!world_manager.can_mesh(&IVec3::ZERO));
+        let before = face_occlusion(&world_manager, IVec3::ZERO);
+
+        world_manager.pending_remesh.clear();
+        world_manager.insert_generated(generated(diagonal), &TerrainGenerator::sunken());
+
+        assert!(world_manager.can_mesh(&IVec3::ZERO));
+        assert!(world_manager.pending_remesh.contains(&IVec3::ZERO));
+
+        let after = face_occlusion(&world_manager, IVec3::ZERO);
+        let top = (IVec3::new(31, 0, 31), FaceDirection::PosY);
+        assert_eq!(before.0[&top], [3; 4]);
+        assert!(after.0[&top].contains(&2), "{:?}", after.0[&top]);
+    }
+
+    #[test]
+    fn edits_on_chunk_faces_remesh_the_bordering_chunk() {
+        let mut world_manager = WorldManager::default();
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let mut chunk_data = ChunkData::new(IVec3::new(x, y, z));
+                    chunk_data.generated = true;
+                    world_manager.insert_generated(chunk_data, &TerrainGenerator::sunken());
+                }
+            }
+        }
+
+        world_manager.pending_remesh.clear();
+        assert!(world_manager.set_voxel(IVec3::new(31, 5, 5), Voxel::Stone));
+
+        assert!(world_manager.pending_remesh.contains(&IVec3::ZERO));
+        assert!(world_manager.pending_remesh.contains(&IVec3::X));
+        assert!(!world_manager.pending_remesh.contains(&IVec3::NEG_X));
+    }
+
+    #[test]
+    fn neighbors_of_meshed_chunks_are_generated() {
+        let loading_settings = ChunkLoadingSettings::default();
+        let horizontal = loading_settings.horizontal_distance;
+        let vertical = loading_settings.vertical_distance;
+
+        for x in -horizontal..=horizontal {
+            for y in -vertical..=vertical {
+                for z in -horizontal..=horizontal {
+                    let offset = IVec3::new(x, y, z);
+                    if !loading_settings.is_meshed(offset) {
+                        continue;
+                    }
+
+                    for neighbor in 0..27 {
+                        let neighbor = IVec3::new(neighbor % 3, neighbor / 3 % 3, neighbor / 9) - 1;
+                        assert!(loading_settings.is_generated(offset + neighbor));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn world_plugin_opens_the_world_manifest() {
+        let directory = TemporaryDirectory::new("world-plugin");
+        let world_config = WorldConfig {
+            seed: 7,
+            directory: directory.0.join("world"),
+            ..default()
+        };
+
+        let mut app = App::new();
+        app.insert_resource(world_config.clone())
+            .add_plugins(WorldPlugin);
+
+        assert_eq!(app.world().resource::<WorldManifest>().seed, 7);
+        assert!(world_config.directory.join(MANIFEST_FILE).exists());
+
+        // Reopening keeps the seed the world was created with
+        let mut app = App::new();
+        app.insert_resource(WorldConfig {
+            seed: 8,
+            ..world_config
+        })
+        .add_plugins(WorldPlugin);
+
+        assert_eq!(app.world().resource::<WorldManifest>().seed, 7);
+        assert_eq!(app.world().resource::<WorldConfig>().seed, 7);
+    }
+
+    fn world_directory(name: &str) -> Result<PathBuf, String> {
+        WorldConfig::from_args(["--world".to_string(), name.to_string()])
+            .map(|config| config.directory)
+    }
+
+    #[test]
+    fn world_names_stay_inside_the_saves_directory() {
+        assert_eq!(
+            world_directory("my_world"),
+            Ok(PathBuf::from(SAVES_DIRECTORY).join("my_world"))
+        );
+
+        for name in ["", ".", "..", "../other", "a/b", "a/", "/tmp/world"] {
+            assert!(world_directory(name).is_err(), "accepted `{name}`");
+        }
+    }
 }